@@ -0,0 +1,207 @@
+use crate::common::{ ClientError, ClientErrorType };
+use crate::http::HttpResponse;
+use crate::http::parser::Parser;
+
+const WWW_AUTHENTICATE: &str = "WWW-Authenticate";
+
+/**
+ * Authentication challenge sent by a server in WWW-Authenticate, as described in RFC 9110 section 11.
+ * A challenge has either auth parameters or a token68.
+ * Example: Digest realm="api", nonce="abc", qop="auth"
+ */
+#[derive(Debug, PartialEq)]
+pub struct Challenge {
+    pub scheme: String,
+    pub params: Vec<(String, String)>,
+    pub token68: Option<String>
+}
+
+impl Challenge {
+    /**
+     * Get the value of the auth parameter. Names are compared case insensitive.
+     */
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|param| param.0.eq_ignore_ascii_case(name))
+            .map(|param| param.1.as_str())
+    }
+
+    /**
+     * Returns true if the challenge is for the auth scheme. Schemes are compared case insensitive.
+     */
+    pub fn is_scheme(&self, scheme: &str) -> bool {
+        self.scheme.eq_ignore_ascii_case(scheme)
+    }
+
+    /**
+     * Get the challenges of all WWW-Authenticate fields of a response, in order.
+     */
+    pub fn from_response(http_response: &HttpResponse) -> Result<Vec<Challenge>, ClientError> {
+        let mut challenges: Vec<Challenge> = Vec::new();
+        for value in http_response.headers.get_all(WWW_AUTHENTICATE) {
+            challenges.extend(parse_challenges(value)?);
+        }
+        Ok(challenges)
+    }
+}
+
+/**
+ * Parses the challenges of a WWW-Authenticate field value.
+ * A field value can contain several comma separated challenges.
+ * Example: Basic realm="api", Digest realm="api", nonce="abc"
+ */
+pub fn parse_challenges(value: &str) -> Result<Vec<Challenge>, ClientError> {
+    let mut parser = Parser::new(value);
+    let mut challenges: Vec<Challenge> = Vec::new();
+    loop {
+        parser.skip_separators();
+        if parser.is_end() {
+            return Ok(challenges);
+        }
+        let scheme = parser.read_token();
+        if scheme.is_empty() {
+            return Err(ClientError::new(ClientErrorType::InvalidHeader, format!("Invalid challenge {:?}", value)));
+        }
+        let mut challenge = Challenge { scheme, params: Vec::new(), token68: None };
+        parser.parse_auth_params(&mut challenge)?;
+        challenges.push(challenge);
+    }
+}
+
+/**
+ * Methods of the parser for challenges.
+ */
+impl Parser<'_> {
+    fn skip_separators(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b',')) {
+            self.position += 1;
+        }
+    }
+
+    fn read_token(&mut self) -> String {
+        self.read_while(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+    }
+
+    /**
+     * Reads a quoted string, removing the quotes and escapes.
+     */
+    fn read_quoted_string(&mut self) -> Result<String, ClientError> {
+        let mut value: Vec<u8> = Vec::new();
+        self.position += 1;
+        loop {
+            match self.peek() {
+                None => return Err(ClientError::new(ClientErrorType::InvalidHeader, "Unterminated quoted string in challenge".to_string())),
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(String::from_utf8_lossy(&value).to_string());
+                },
+                Some(b'\\') if self.position + 1 < self.input.len() => {
+                    value.push(self.input[self.position + 1]);
+                    self.position += 2;
+                },
+                Some(byte) => {
+                    value.push(byte);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    /**
+     * Parses the auth parameters or token68 following an auth scheme.
+     * Stops before an item that is not an auth parameter, which starts the next challenge.
+     */
+    fn parse_auth_params(&mut self, challenge: &mut Challenge) -> Result<(), ClientError> {
+        self.skip_whitespace();
+        if !self.try_auth_param(challenge)? {
+            let start = self.position;
+            let token68 = self.read_while(|byte| byte.is_ascii_alphanumeric() || b"-._~+/".contains(&byte));
+            let padding = self.read_while(|byte| byte == b'=');
+            self.skip_whitespace();
+            if !token68.is_empty() && (self.is_end() || self.peek() == Some(b',')) {
+                challenge.token68 = Some(token68 + &padding);
+                return Ok(());
+            }
+            self.position = start;
+        }
+        loop {
+            let start = self.position;
+            self.skip_separators();
+            if self.is_end() || !self.try_auth_param(challenge)? {
+                self.position = start;
+                return Ok(());
+            }
+        }
+    }
+
+    /**
+     * Parses an auth parameter if the input is at one.
+     * Returns false and leaves the position unchanged if it is not.
+     */
+    fn try_auth_param(&mut self, challenge: &mut Challenge) -> Result<bool, ClientError> {
+        let start = self.position;
+        let name = self.read_token();
+        self.skip_whitespace();
+        if name.is_empty() || !self.consume(b'=') {
+            self.position = start;
+            return Ok(false);
+        }
+        self.skip_whitespace();
+        let value = match self.peek() {
+            Some(b'"') => self.read_quoted_string()?,
+            _ => {
+                let value = self.read_token();
+                if value.is_empty() {
+                    self.position = start;
+                    return Ok(false);
+                }
+                value
+            }
+        };
+        challenge.params.push((name, value));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn get_params(params: &[(&str, &str)]) -> Vec<(String, String)> {
+        params.iter().map(|param| (param.0.to_string(), param.1.to_string())).collect()
+    }
+
+    #[test]
+    fn test_parse_challenges() {
+        let challenges = parse_challenges("Basic realm=\"simple\", Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=SHA-256, nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\"").unwrap();
+        assert_eq!(challenges.len(), 2);
+        assert_eq!(challenges[0].scheme, "Basic");
+        assert_eq!(challenges[0].params, get_params(&[("realm", "simple")]));
+        assert!(challenges[1].is_scheme("digest"));
+        assert_eq!(challenges[1].get_param("QOP"), Some("auth, auth-int"));
+        assert_eq!(challenges[1].get_param("algorithm"), Some("SHA-256"));
+        assert_eq!(challenges[1].get_param("nonce"), Some("7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v"));
+    }
+
+    #[test]
+    fn test_parse_challenges_token68_and_empty() {
+        let challenges = parse_challenges("Negotiate, NTLM abc+/de==, Bearer realm=\"a \\\"b\\\"\", error=invalid_token").unwrap();
+        assert_eq!(challenges.len(), 3);
+        assert_eq!(challenges[0], Challenge { scheme: "Negotiate".to_string(), params: Vec::new(), token68: None });
+        assert_eq!(challenges[1].token68, Some("abc+/de==".to_string()));
+        assert_eq!(challenges[2].params, get_params(&[("realm", "a \"b\""), ("error", "invalid_token")]));
+    }
+
+    #[test]
+    fn test_parse_challenges_whitespace_around_equals() {
+        let challenges = parse_challenges("Digest realm = \"x\" , nonce=\"\"").unwrap();
+        assert_eq!(challenges[0].params, get_params(&[("realm", "x"), ("nonce", "")]));
+    }
+
+    #[test]
+    fn test_parse_challenges_invalid() {
+        assert!(parse_challenges("Digest realm=\"unterminated").is_err());
+        assert!(parse_challenges("\"quoted\"").is_err());
+        assert!(parse_challenges("").unwrap().is_empty());
+    }
+}
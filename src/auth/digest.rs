@@ -0,0 +1,487 @@
+use std::fmt;
+
+use md5::Md5;
+use sha2::{ Digest, Sha256, Sha512_256 };
+
+use crate::auth::{ fill_random, get_retry_request, BasicCredentials };
+use crate::auth::challenge::Challenge;
+use crate::common::{ ClientError, ClientErrorType };
+use crate::common::urlencode::{ self, EncodeSet };
+use crate::connection::PoolKey;
+use crate::http::{ HttpClient, HttpRequest, HttpResponse, RequestBody };
+
+const AUTHORIZATION: &str = "Authorization";
+const DIGEST: &str = "Digest";
+const UNAUTHORIZED: u16 = 401;
+const CNONCE_LENGTH: usize = 16;
+
+/**
+ * Hash algorithm of a digest challenge, as described in RFC 7616 section 3.3.
+ * The session variants hash the nonce and cnonce into the user hash.
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha256,
+    Sha512_256
+}
+
+impl DigestAlgorithm {
+    /**
+     * Get the algorithm and whether it is a session variant from the algorithm parameter.
+     * Returns None if the algorithm is not supported. A missing parameter means MD5.
+     */
+    fn parse(algorithm: Option<&str>) -> Option<(DigestAlgorithm, bool)> {
+        let algorithm = algorithm.unwrap_or("MD5").to_ascii_uppercase();
+        let (name, session) = match algorithm.strip_suffix("-SESS") {
+            Some(name) => (name.to_string(), true),
+            None => (algorithm, false)
+        };
+        match name.as_str() {
+            "MD5" => Some((DigestAlgorithm::Md5, session)),
+            "SHA-256" => Some((DigestAlgorithm::Sha256, session)),
+            "SHA-512-256" => Some((DigestAlgorithm::Sha512_256, session)),
+            _ => None
+        }
+    }
+
+    fn get_name(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Md5 => "MD5",
+            DigestAlgorithm::Sha256 => "SHA-256",
+            DigestAlgorithm::Sha512_256 => "SHA-512-256"
+        }
+    }
+
+    /**
+     * Hashes the data and returns the hash as lowercase hex.
+     */
+    fn hash(&self, data: &[u8]) -> String {
+        let hash = match self {
+            DigestAlgorithm::Md5 => Md5::digest(data).to_vec(),
+            DigestAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            DigestAlgorithm::Sha512_256 => Sha512_256::digest(data).to_vec()
+        };
+        hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/**
+ * Quality of protection, as described in RFC 7616 section 3.4.
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Qop {
+    // No qop, the RFC 2069 compatible response.
+    None,
+    // Authentication of the method and uri.
+    Auth,
+    // Authentication of the method, uri and body.
+    AuthInt
+}
+
+/**
+ * Digest challenge the responses are computed for.
+ */
+#[derive(Debug, Clone)]
+struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: DigestAlgorithm,
+    session: bool,
+    qop_options: Vec<String>,
+    userhash: bool,
+    stale: bool
+}
+
+impl DigestChallenge {
+    /**
+     * Get the digest challenge from a challenge.
+     * Returns None if the challenge is not a digest challenge or the algorithm is not supported.
+     */
+    fn from_challenge(challenge: &Challenge) -> Result<Option<DigestChallenge>, ClientError> {
+        if !challenge.is_scheme(DIGEST) {
+            return Ok(None);
+        }
+        let (algorithm, session) = match DigestAlgorithm::parse(challenge.get_param("algorithm")) {
+            Some(algorithm) => algorithm,
+            None => return Ok(None)
+        };
+        let (realm, nonce) = match (challenge.get_param("realm"), challenge.get_param("nonce")) {
+            (Some(realm), Some(nonce)) => (realm.to_string(), nonce.to_string()),
+            _ => return Err(ClientError::new(ClientErrorType::InvalidHeader, "Digest challenge without realm or nonce".to_string()))
+        };
+        Ok(Some(DigestChallenge {
+            realm,
+            nonce,
+            opaque: challenge.get_param("opaque").map(str::to_string),
+            algorithm,
+            session,
+            qop_options: challenge.get_param("qop").unwrap_or_default().split(',').map(|qop| qop.trim().to_ascii_lowercase()).filter(|qop| !qop.is_empty()).collect(),
+            userhash: challenge.get_param("userhash").is_some_and(|userhash| userhash.eq_ignore_ascii_case("true")),
+            stale: challenge.get_param("stale").is_some_and(|stale| stale.eq_ignore_ascii_case("true"))
+        }))
+    }
+
+    /**
+     * Get the qop to use for a request. auth is preferred, auth-int is used if it is the only
+     * option and the body can be hashed.
+     */
+    fn get_qop(&self, body: &Option<RequestBody>) -> Result<Qop, ClientError> {
+        if self.qop_options.is_empty() {
+            Ok(Qop::None)
+        } else if self.qop_options.iter().any(|qop| qop == "auth") {
+            Ok(Qop::Auth)
+        } else if self.qop_options.iter().any(|qop| qop == "auth-int") && !matches!(body, Some(RequestBody::Stream(_))) {
+            Ok(Qop::AuthInt)
+        } else {
+            Err(ClientError::new(ClientErrorType::Unimplemented, format!("Unsupported qop {:?}", self.qop_options)))
+        }
+    }
+}
+
+/**
+ * State of the digest authentication with the server, the last challenge and the number of
+ * requests sent with its nonce.
+ */
+#[derive(Debug)]
+struct DigestState {
+    challenge: DigestChallenge,
+    nonce_count: u32,
+    cnonce: String
+}
+
+/**
+ * Digest access authentication as described in RFC 7616.
+ * The first request is sent without credentials. When the server responds 401 with a digest
+ * challenge, the request is sent again on the same client with the response to the challenge.
+ * Later requests reuse the nonce with an increasing nonce count until the server sends a new challenge.
+ * The credentials are only sent to their origin.
+ */
+pub struct DigestAuth {
+    credentials: BasicCredentials,
+    state: Option<DigestState>
+}
+
+impl DigestAuth {
+    /**
+     * Creates digest authentication with the user and password of the credentials.
+     */
+    pub fn new(credentials: BasicCredentials) -> DigestAuth {
+        DigestAuth {
+            credentials,
+            state: None
+        }
+    }
+
+    /**
+     * Sends the request to the origin, answering a digest challenge by sending the request again.
+     * A request with a streamed body cannot be sent again, so the 401 response is returned.
+     * Returns the 401 response if the server rejects the credentials.
+     */
+    pub fn send(&mut self, http_client: &mut HttpClient, mut http_request: HttpRequest, origin: &PoolKey) -> Result<HttpResponse, ClientError> {
+        if origin != self.credentials.get_origin() || http_request.headers.contains(AUTHORIZATION) {
+            return http_client.send(http_request);
+        }
+        let retry_request = get_retry_request(&http_request);
+        let authorized = self.authorize(&mut http_request)?;
+        let http_response = http_client.send(http_request)?;
+        if http_response.response_code != UNAUTHORIZED || !self.handle_challenge(&http_response, authorized)? {
+            return Ok(http_response);
+        }
+        match retry_request {
+            Some(mut retry_request) => {
+                self.authorize(&mut retry_request)?;
+                http_client.send(retry_request)
+            },
+            None => Ok(http_response)
+        }
+    }
+
+    /**
+     * Adds the Authorization header if a challenge has been received.
+     * Returns true if the header was added.
+     */
+    pub fn authorize(&mut self, http_request: &mut HttpRequest) -> Result<bool, ClientError> {
+        let state = match &mut self.state {
+            Some(state) => state,
+            None => return Ok(false)
+        };
+        state.nonce_count += 1;
+        let authorization = get_authorization(&self.credentials, state, http_request)?;
+        http_request.headers.insert(AUTHORIZATION, &authorization);
+        Ok(true)
+    }
+
+    /**
+     * Stores the first supported digest challenge of a 401 response.
+     * Returns true if the request should be sent again, which is when the request was sent
+     * without credentials or the server says the nonce was stale. Otherwise the credentials
+     * were rejected.
+     */
+    pub fn handle_challenge(&mut self, http_response: &HttpResponse, authorized: bool) -> Result<bool, ClientError> {
+        let mut digest_challenge: Option<DigestChallenge> = None;
+        for challenge in Challenge::from_response(http_response)? {
+            if let Some(challenge) = DigestChallenge::from_challenge(&challenge)? {
+                digest_challenge = Some(challenge);
+                break;
+            }
+        }
+        let challenge = match digest_challenge {
+            Some(challenge) => challenge,
+            None => return Ok(false)
+        };
+        let retry = !authorized || challenge.stale;
+        self.state = Some(DigestState {
+            challenge,
+            nonce_count: 0,
+            cnonce: get_cnonce()?
+        });
+        Ok(retry)
+    }
+}
+
+impl fmt::Debug for DigestAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DigestAuth")
+            .field("credentials", &self.credentials)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+/**
+ * Get the value of the Authorization header for the request, as described in RFC 7616 section 3.4.
+ */
+fn get_authorization(credentials: &BasicCredentials, state: &DigestState, http_request: &HttpRequest) -> Result<String, ClientError> {
+    let challenge = &state.challenge;
+    let qop = challenge.get_qop(&http_request.body)?;
+    let nonce_count = format!("{:08x}", state.nonce_count);
+    let response = get_response(credentials, state, &nonce_count, qop, http_request);
+    let mut authorization = String::from("Digest ");
+    if challenge.userhash {
+        authorization.push_str(&format!("username=\"{}\"", challenge.algorithm.hash(format!("{}:{}", credentials.user, challenge.realm).as_bytes())));
+    } else if credentials.user.is_ascii() && !credentials.user.contains(['"', '\\']) {
+        authorization.push_str(&format!("username=\"{}\"", credentials.user));
+    } else {
+        authorization.push_str(&format!("username*=UTF-8''{}", urlencode::encode_with(&credentials.user, EncodeSet::Component)));
+    }
+    authorization.push_str(&format!(", realm=\"{}\", uri=\"{}\"", quote(&challenge.realm), quote(&http_request.path)));
+    authorization.push_str(&format!(", algorithm={}{}", challenge.algorithm.get_name(), if challenge.session { "-sess" } else { "" }));
+    authorization.push_str(&format!(", nonce=\"{}\"", quote(&challenge.nonce)));
+    if qop != Qop::None {
+        authorization.push_str(&format!(", nc={}, cnonce=\"{}\", qop={}", nonce_count, state.cnonce, get_qop_name(qop)));
+    }
+    authorization.push_str(&format!(", response=\"{}\"", response));
+    if let Some(opaque) = &challenge.opaque {
+        authorization.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+    }
+    if challenge.userhash {
+        authorization.push_str(", userhash=true");
+    }
+    Ok(authorization)
+}
+
+/**
+ * Computes the response to the challenge.
+ */
+fn get_response(credentials: &BasicCredentials, state: &DigestState, nonce_count: &str, qop: Qop, http_request: &HttpRequest) -> String {
+    let challenge = &state.challenge;
+    let algorithm = challenge.algorithm;
+    let mut ha1 = algorithm.hash(format!("{}:{}:{}", credentials.user, challenge.realm, credentials.get_password()).as_bytes());
+    if challenge.session {
+        ha1 = algorithm.hash(format!("{}:{}:{}", ha1, challenge.nonce, state.cnonce).as_bytes());
+    }
+    let ha2 = match (qop, &http_request.body) {
+        (Qop::AuthInt, Some(RequestBody::Bytes(body))) => algorithm.hash(format!("{}:{}:{}", http_request.method, http_request.path, algorithm.hash(body)).as_bytes()),
+        (Qop::AuthInt, _) => algorithm.hash(format!("{}:{}:{}", http_request.method, http_request.path, algorithm.hash(b"")).as_bytes()),
+        _ => algorithm.hash(format!("{}:{}", http_request.method, http_request.path).as_bytes())
+    };
+    match qop {
+        Qop::None => algorithm.hash(format!("{}:{}:{}", ha1, challenge.nonce, ha2).as_bytes()),
+        _ => algorithm.hash(format!("{}:{}:{}:{}:{}:{}", ha1, challenge.nonce, nonce_count, state.cnonce, get_qop_name(qop), ha2).as_bytes())
+    }
+}
+
+fn get_qop_name(qop: Qop) -> &'static str {
+    match qop {
+        Qop::None => "",
+        Qop::Auth => "auth",
+        Qop::AuthInt => "auth-int"
+    }
+}
+
+/**
+ * Escapes quotes and backslashes in a quoted string.
+ */
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/**
+ * Get a random client nonce as hex.
+ */
+fn get_cnonce() -> Result<String, ClientError> {
+    let mut bytes = [0u8; CNONCE_LENGTH];
+    fill_random(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{ BufRead, BufReader, Write };
+    use std::net::TcpListener;
+    use std::thread;
+    use crate::auth::challenge::parse_challenges;
+    use crate::http::HeaderMap;
+
+    const RFC_7616_CHALLENGE: &str = "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=ALGORITHM, nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+    const RFC_7616_CNONCE: &str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+    fn get_state(challenge: &str, cnonce: &str) -> DigestState {
+        let challenge = DigestChallenge::from_challenge(&parse_challenges(challenge).unwrap()[0]).unwrap().unwrap();
+        DigestState { challenge, nonce_count: 1, cnonce: cnonce.to_string() }
+    }
+
+    fn get_credentials(user: &str, password: &str) -> BasicCredentials {
+        BasicCredentials::new(user.to_string(), password.to_string(), PoolKey::new("http", "127.0.0.1", 80)).unwrap()
+    }
+
+    fn get_request(method: &str, path: &str, body: Option<RequestBody>) -> HttpRequest {
+        HttpRequest::new(path.to_string(), method.to_string(), "localhost".to_string(), HeaderMap::new(), body)
+    }
+
+    #[test]
+    fn test_response_rfc_7616_md5() {
+        let state = get_state(&RFC_7616_CHALLENGE.replace("ALGORITHM", "MD5"), RFC_7616_CNONCE);
+        let response = get_response(&get_credentials("Mufasa", "Circle of Life"), &state, "00000001", Qop::Auth, &get_request("GET", "/dir/index.html", None));
+        assert_eq!(response, "8ca523f5e9506fed4657c9700eebdbec");
+    }
+
+    #[test]
+    fn test_response_rfc_7616_sha256() {
+        let state = get_state(&RFC_7616_CHALLENGE.replace("ALGORITHM", "SHA-256"), RFC_7616_CNONCE);
+        let authorization = get_authorization(&get_credentials("Mufasa", "Circle of Life"), &state, &get_request("GET", "/dir/index.html", None)).unwrap();
+        assert_eq!(authorization, "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", uri=\"/dir/index.html\", algorithm=SHA-256, nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", nc=00000001, cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth, response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\", opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"");
+    }
+
+    #[test]
+    fn test_response_rfc_2617() {
+        let state = get_state("Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"", "0a4f113b");
+        let response = get_response(&get_credentials("Mufasa", "Circle Of Life"), &state, "00000001", Qop::Auth, &get_request("GET", "/dir/index.html", None));
+        assert_eq!(response, "6629fae49393a05397450978507c4ef1");
+        let state = get_state("Digest realm=\"testrealm@host.com\", nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\"", "0a4f113b");
+        let response = get_response(&get_credentials("Mufasa", "Circle Of Life"), &state, "00000001", Qop::None, &get_request("GET", "/dir/index.html", None));
+        assert_eq!(response, "670fd8c2df070c60b045671b8b24ff02");
+    }
+
+    #[test]
+    fn test_response_auth_int_and_session() {
+        let state = get_state("Digest realm=\"r\", qop=\"auth-int\", algorithm=SHA-512-256-sess, nonce=\"n\"", "c");
+        let http_request = get_request("POST", "/", Some(RequestBody::from_string("body".to_string())));
+        let algorithm = DigestAlgorithm::Sha512_256;
+        let ha1 = algorithm.hash(format!("{}:n:c", algorithm.hash(b"user:r:password")).as_bytes());
+        let ha2 = algorithm.hash(format!("POST:/:{}", algorithm.hash(b"body")).as_bytes());
+        let expected = algorithm.hash(format!("{}:n:00000001:c:auth-int:{}", ha1, ha2).as_bytes());
+        assert_eq!(state.challenge.get_qop(&http_request.body).unwrap(), Qop::AuthInt);
+        assert_eq!(get_response(&get_credentials("user", "password"), &state, "00000001", Qop::AuthInt, &http_request), expected);
+        let authorization = get_authorization(&get_credentials("user", "password"), &state, &http_request).unwrap();
+        assert!(authorization.contains("algorithm=SHA-512-256-sess"));
+        assert!(authorization.contains("qop=auth-int"));
+    }
+
+    #[test]
+    fn test_sha512_256_hash() {
+        assert_eq!(DigestAlgorithm::Sha512_256.hash(b"abc"), "53048e2681941ef99b2e29b76b4c7dabe4c2d0c634fc6d46e0e2f13107e7af23");
+    }
+
+    #[test]
+    fn test_userhash_and_utf8_username() {
+        let state = get_state("Digest realm=\"api@example.org\", qop=auth, algorithm=SHA-512-256, nonce=\"n\", userhash=true", "c");
+        let authorization = get_authorization(&get_credentials("J\u{e4}s\u{f8}n Doe", "Secret, or not?"), &state, &get_request("GET", "/doe.json", None)).unwrap();
+        assert!(authorization.starts_with("Digest username=\"793263caabb707a56211940d90411ea4a575adeccb7e360aeb624ed06ece9b0b\""));
+        assert!(authorization.ends_with(", userhash=true"));
+        let state = get_state("Digest realm=\"api@example.org\", qop=auth, nonce=\"n\"", "c");
+        let authorization = get_authorization(&get_credentials("J\u{e4}s\u{f8}n Doe", "Secret, or not?"), &state, &get_request("GET", "/doe.json", None)).unwrap();
+        assert!(authorization.starts_with("Digest username*=UTF-8''J%C3%A4s%C3%B8n%20Doe, "));
+    }
+
+    #[test]
+    fn test_unsupported_challenges() {
+        let challenges = parse_challenges("Digest realm=\"r\", nonce=\"n\", algorithm=SHA-1, Basic realm=\"r\"").unwrap();
+        assert!(DigestChallenge::from_challenge(&challenges[0]).unwrap().is_none());
+        assert!(DigestChallenge::from_challenge(&challenges[1]).unwrap().is_none());
+        let challenges = parse_challenges("Digest realm=\"r\"").unwrap();
+        assert!(DigestChallenge::from_challenge(&challenges[0]).is_err());
+        let state = get_state("Digest realm=\"r\", nonce=\"n\", qop=auth-int", "c");
+        let body = Some(RequestBody::Stream(Box::new(std::io::empty())));
+        assert_eq!(state.challenge.get_qop(&body).err().unwrap().error_type, ClientErrorType::Unimplemented);
+    }
+
+    /**
+     * Starts a server that answers requests without Authorization with a digest challenge,
+     * and requests with Authorization with 200 and the Authorization value as body.
+     */
+    fn start_server(requests: usize) -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut authorizations: Vec<String> = Vec::new();
+            for _ in 0..requests {
+                let mut authorization = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("Authorization: ") {
+                        authorization = value.trim_end().to_string();
+                    }
+                }
+                if authorization.is_empty() {
+                    writer.write_all(b"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"r\"\r\nWWW-Authenticate: Digest realm=\"r\", qop=\"auth\", algorithm=SHA-256, nonce=\"n1\"\r\nContent-Length: 0\r\n\r\n").unwrap();
+                } else {
+                    writer.write_all(format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", authorization.len(), authorization).as_bytes()).unwrap();
+                }
+                authorizations.push(authorization);
+            }
+            authorizations
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn test_send_replays_on_same_client() {
+        let (port, handle) = start_server(3);
+        let origin = PoolKey::new("http", "127.0.0.1", port);
+        let credentials = BasicCredentials::new("user".to_string(), "password".to_string(), origin.clone()).unwrap();
+        let mut digest_auth = DigestAuth::new(credentials);
+        let mut http_client = HttpClient::new("127.0.0.1".to_string(), port, std::time::Duration::from_secs(1));
+        let http_response = digest_auth.send(&mut http_client, get_request("GET", "/", None), &origin).unwrap();
+        assert_eq!(http_response.response_code, 200);
+        assert!(http_response.get_body_string().contains("nc=00000001"));
+        let http_response = digest_auth.send(&mut http_client, get_request("GET", "/", None), &origin).unwrap();
+        assert!(http_response.get_body_string().contains("nc=00000002"));
+        let authorizations = handle.join().unwrap();
+        assert_eq!(authorizations.len(), 3);
+        assert!(authorizations[0].is_empty());
+        assert!(authorizations[1].starts_with("Digest username=\"user\", realm=\"r\", uri=\"/\", algorithm=SHA-256, nonce=\"n1\", nc=00000001"));
+    }
+
+    #[test]
+    fn test_send_other_origin() {
+        let (port, handle) = start_server(1);
+        let credentials = BasicCredentials::new("user".to_string(), "password".to_string(), PoolKey::new("http", "localhost", port)).unwrap();
+        let mut digest_auth = DigestAuth::new(credentials);
+        let mut http_client = HttpClient::new("127.0.0.1".to_string(), port, std::time::Duration::from_secs(1));
+        let http_response = digest_auth.send(&mut http_client, get_request("GET", "/", None), &PoolKey::new("http", "127.0.0.1", port)).unwrap();
+        assert_eq!(http_response.response_code, 401);
+        assert!(digest_auth.state.is_none());
+        handle.join().unwrap();
+    }
+}
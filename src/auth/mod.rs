@@ -0,0 +1,52 @@
+mod basic;
+mod challenge;
+mod digest;
+mod message_signature;
+mod oauth;
+mod sigv4;
+
+pub use basic::BasicCredentials;
+pub use challenge::{ parse_challenges, Challenge };
+pub use digest::{ DigestAlgorithm, DigestAuth };
+pub use message_signature::{ MessageSigner, SignatureAlgorithm, SignatureParams, SignatureVerifier, SigningKey, VerifyingKey };
+pub use oauth::{ OAuthAuth, OAuthConfig, OAuthFlow, OAuthToken, TokenCache };
+pub use sigv4::{ AwsCredentials, SigV4Signer };
+
+use crate::common::{ ClientError, ClientErrorType };
+use crate::http::HttpRequest;
+
+/**
+ * Fills the buffer with bytes from a secure random source, for nonces and verifiers.
+ */
+pub(crate) fn fill_random(bytes: &mut [u8]) -> Result<(), ClientError> {
+    match rustls::crypto::aws_lc_rs::default_provider().secure_random.fill(bytes) {
+        Ok(()) => Ok(()),
+        Err(_) => Err(ClientError::new(ClientErrorType::RandomError, "The secure random source failed".to_string()))
+    }
+}
+
+/**
+ * Get a copy of the request to send again, or None if the body is streamed.
+ */
+pub(crate) fn get_retry_request(http_request: &HttpRequest) -> Option<HttpRequest> {
+    let body = match &http_request.body {
+        Some(body) => Some(body.try_clone()?),
+        None => None
+    };
+    Some(HttpRequest::new(http_request.path.clone(), http_request.method.clone(), http_request.host.clone(), http_request.headers.clone(), body))
+}
+
+/**
+ * Splits the request target into path and query. An absolute target, sent to a proxy, is
+ * reduced to its path.
+ */
+pub(crate) fn split_target(target: &str) -> Result<(String, String), ClientError> {
+    if target.starts_with('/') {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        return Ok((path.to_string(), query.to_string()));
+    }
+    match url::Url::parse(target) {
+        Ok(url) => Ok((url.path().to_string(), url.query().unwrap_or_default().to_string())),
+        Err(_) => Err(ClientError::new(ClientErrorType::UnparseableUrl, format!("Request target {:?} could not be parsed", target)))
+    }
+}
@@ -0,0 +1,1039 @@
+use std::fmt;
+use std::fs::{ self, OpenOptions };
+use std::io::{ BufRead, BufReader, ErrorKind, Write };
+use std::net::{ TcpListener, TcpStream };
+use std::path::{ Path, PathBuf };
+use std::thread;
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+
+use clap::ValueEnum;
+use serde_json::{ Map, Value };
+use sha2::{ Digest, Sha256 };
+use url::Url;
+
+use crate::auth::{ fill_random, get_retry_request };
+use crate::common::{ ClientError, ClientErrorType };
+use crate::common::base64;
+use crate::common::urlencode::{ self, EncodeSet };
+use crate::connection::{ PoolKey, TlsConnection, TlsSettings };
+use crate::http::{ HeaderMap, HttpClient, HttpRequest, HttpResponse, RequestBody };
+
+const AUTHORIZATION: &str = "Authorization";
+const CONTENT_TYPE: &str = "Content-Type";
+const ACCEPT: &str = "Accept";
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+const JSON_CONTENT_TYPE: &str = "application/json";
+const BEARER: &str = "Bearer";
+const UNAUTHORIZED: u16 = 401;
+const CALLBACK_PATH: &str = "/callback";
+/**
+ * Seconds before the expiry of a token it is refreshed, so it does not expire in flight.
+ */
+const EXPIRY_LEEWAY: u64 = 30;
+/**
+ * Time to wait for the browser to be redirected to the loopback listener.
+ */
+const REDIRECT_TIMEOUT: Duration = Duration::from_secs(300);
+const VERIFIER_LENGTH: usize = 32;
+const STATE_LENGTH: usize = 16;
+
+/**
+ * Grant used to get an access token, as described in RFC 6749 section 4 and 6.
+ */
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OAuthFlow {
+    // Client credentials grant, the client authenticates as itself.
+    ClientCredentials,
+    // Authorization code grant with PKCE, the user authorizes the client in a browser.
+    AuthorizationCode,
+    // Refresh token grant, a refresh token is exchanged for an access token.
+    RefreshToken,
+}
+
+/**
+ * Configuration of the authorization server and client.
+ * The authorization url and a handler showing it to the user are required by the authorization
+ * code flow, and the refresh token by the refresh token flow. Tokens are cached in the token
+ * cache file if one is given.
+ */
+#[derive(Clone)]
+pub struct OAuthConfig {
+    pub flow: OAuthFlow,
+    pub token_url: Url,
+    pub authorization_url: Option<Url>,
+    authorization_url_handler: Option<fn(&Url)>,
+    pub client_id: String,
+    client_secret: Option<String>,
+    pub scope: Option<String>,
+    refresh_token: Option<String>,
+    pub redirect_port: u16,
+    pub token_cache: Option<PathBuf>,
+    pub connection_timeout: Duration,
+    pub tls_settings: TlsSettings
+}
+
+impl OAuthConfig {
+    /**
+     * Creates the configuration for the flow with the token endpoint and client id.
+     * Other settings are added with the with_ methods.
+     */
+    pub fn new(flow: OAuthFlow, token_url: Url, client_id: String, connection_timeout: Duration) -> OAuthConfig {
+        OAuthConfig {
+            flow,
+            token_url,
+            authorization_url: None,
+            authorization_url_handler: None,
+            client_id,
+            client_secret: None,
+            scope: None,
+            refresh_token: None,
+            redirect_port: 0,
+            token_cache: None,
+            connection_timeout,
+            tls_settings: TlsSettings::default()
+        }
+    }
+
+    /**
+     * Sets the client secret. A client with a secret authenticates with basic authentication
+     * as described in RFC 6749 section 2.3.1, a client without sends its client id in the request.
+     */
+    pub fn with_client_secret(mut self, client_secret: Option<String>) -> OAuthConfig {
+        self.client_secret = client_secret;
+        self
+    }
+
+    pub fn with_scope(mut self, scope: Option<String>) -> OAuthConfig {
+        self.scope = scope;
+        self
+    }
+
+    pub fn with_authorization_url(mut self, authorization_url: Option<Url>) -> OAuthConfig {
+        self.authorization_url = authorization_url;
+        self
+    }
+
+    /**
+     * Sets the handler called with the authorization url of the authorization code flow, to show
+     * the url to the user or open it in a browser.
+     */
+    pub fn with_authorization_url_handler(mut self, authorization_url_handler: Option<fn(&Url)>) -> OAuthConfig {
+        self.authorization_url_handler = authorization_url_handler;
+        self
+    }
+
+    pub fn with_refresh_token(mut self, refresh_token: Option<String>) -> OAuthConfig {
+        self.refresh_token = refresh_token;
+        self
+    }
+
+    /**
+     * Sets the port of the loopback listener for the authorization redirect. 0 uses any free port.
+     */
+    pub fn with_redirect_port(mut self, redirect_port: u16) -> OAuthConfig {
+        self.redirect_port = redirect_port;
+        self
+    }
+
+    pub fn with_token_cache(mut self, token_cache: Option<PathBuf>) -> OAuthConfig {
+        self.token_cache = token_cache;
+        self
+    }
+
+    /**
+     * Sets the tls settings used to connect to an https token endpoint.
+     */
+    pub fn with_tls_settings(mut self, tls_settings: TlsSettings) -> OAuthConfig {
+        self.tls_settings = tls_settings;
+        self
+    }
+
+    /**
+     * Returns an error if a setting required by the flow is missing.
+     */
+    pub fn validate(&self) -> Result<(), ClientError> {
+        match self.flow {
+            OAuthFlow::AuthorizationCode if self.authorization_url.is_none() => {
+                Err(ClientError::new(ClientErrorType::InvalidRequest, "The authorization code flow requires an authorization url".to_string()))
+            },
+            OAuthFlow::RefreshToken if self.refresh_token.is_none() => {
+                Err(ClientError::new(ClientErrorType::InvalidRequest, "The refresh token flow requires a refresh token".to_string()))
+            },
+            _ => Ok(())
+        }
+    }
+
+    /**
+     * Get the key of the tokens of this client in the token cache.
+     */
+    fn get_cache_key(&self) -> String {
+        format!("{} {} {}", self.token_url, self.client_id, self.scope.as_deref().unwrap_or_default())
+    }
+}
+
+impl fmt::Debug for OAuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OAuthConfig")
+            .field("flow", &self.flow)
+            .field("token_url", &self.token_url.as_str())
+            .field("authorization_url", &self.authorization_url.as_ref().map(Url::as_str))
+            .field("authorization_url_handler", &self.authorization_url_handler.is_some())
+            .field("client_id", &self.client_id)
+            .field("client_secret", &self.client_secret.as_ref().map(|_| "<redacted>"))
+            .field("scope", &self.scope)
+            .field("refresh_token", &self.refresh_token.as_ref().map(|_| "<redacted>"))
+            .field("redirect_port", &self.redirect_port)
+            .field("token_cache", &self.token_cache)
+            .field("tls_settings", &self.tls_settings)
+            .finish()
+    }
+}
+
+/**
+ * Access token issued by the authorization server, as described in RFC 6749 section 5.1.
+ * The expiry is kept as seconds since the unix epoch so the token can be cached.
+ */
+#[derive(Clone, PartialEq)]
+pub struct OAuthToken {
+    access_token: String,
+    pub token_type: String,
+    pub expires_at: Option<u64>,
+    refresh_token: Option<String>,
+    pub scope: Option<String>
+}
+
+impl OAuthToken {
+    /**
+     * Get the token from a successful token response received at the given time.
+     * Only bearer tokens are supported.
+     */
+    pub fn from_response(body: &[u8], now: u64) -> Result<OAuthToken, ClientError> {
+        let value: Value = match serde_json::from_slice(body) {
+            Ok(value) => value,
+            Err(err) => return Err(ClientError::new(ClientErrorType::AuthorizationFailed, format!("Token response is not json: {}", err)))
+        };
+        let (access_token, token_type) = match (value["access_token"].as_str(), value["token_type"].as_str()) {
+            (Some(access_token), Some(token_type)) => (access_token.to_string(), token_type.to_string()),
+            _ => return Err(ClientError::new(ClientErrorType::AuthorizationFailed, "Token response without access_token or token_type".to_string()))
+        };
+        if !token_type.eq_ignore_ascii_case(BEARER) {
+            return Err(ClientError::new(ClientErrorType::Unimplemented, format!("Unsupported token type {}", token_type)));
+        }
+        let expires_in = match &value["expires_in"] {
+            Value::Number(expires_in) => expires_in.as_u64(),
+            Value::String(expires_in) => expires_in.parse::<u64>().ok(),
+            _ => None
+        };
+        Ok(OAuthToken {
+            access_token,
+            token_type,
+            expires_at: expires_in.map(|expires_in| now.saturating_add(expires_in)),
+            refresh_token: value["refresh_token"].as_str().map(str::to_string),
+            scope: value["scope"].as_str().map(str::to_string)
+        })
+    }
+
+    /**
+     * Returns true if the token has expired, or expires within the leeway, at the given time.
+     * A token without expiry does not expire.
+     */
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now.saturating_add(EXPIRY_LEEWAY) >= expires_at)
+    }
+
+    /**
+     * Get the value of the Authorization header, as described in RFC 6750 section 2.1.
+     * Example: Bearer mF_9.B5f-4.1JqM
+     */
+    pub fn get_authorization(&self) -> String {
+        format!("{} {}", BEARER, self.access_token)
+    }
+
+    fn to_json(&self) -> Value {
+        let mut map = Map::new();
+        map.insert("access_token".to_string(), Value::from(self.access_token.clone()));
+        map.insert("token_type".to_string(), Value::from(self.token_type.clone()));
+        map.insert("expires_at".to_string(), Value::from(self.expires_at));
+        map.insert("refresh_token".to_string(), Value::from(self.refresh_token.clone()));
+        map.insert("scope".to_string(), Value::from(self.scope.clone()));
+        Value::Object(map)
+    }
+
+    fn from_json(value: &Value) -> Option<OAuthToken> {
+        Some(OAuthToken {
+            access_token: value["access_token"].as_str()?.to_string(),
+            token_type: value["token_type"].as_str()?.to_string(),
+            expires_at: value["expires_at"].as_u64(),
+            refresh_token: value["refresh_token"].as_str().map(str::to_string),
+            scope: value["scope"].as_str().map(str::to_string)
+        })
+    }
+}
+
+impl fmt::Debug for OAuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OAuthToken")
+            .field("access_token", &"<redacted>")
+            .field("token_type", &self.token_type)
+            .field("expires_at", &self.expires_at)
+            .field("refresh_token", &self.refresh_token.as_ref().map(|_| "<redacted>"))
+            .field("scope", &self.scope)
+            .finish()
+    }
+}
+
+/**
+ * Tokens cached in a json file, keyed by token endpoint, client id and scope.
+ * The file is only readable by the user on unix, as it contains the tokens.
+ */
+#[derive(Debug, Clone)]
+pub struct TokenCache {
+    path: PathBuf
+}
+
+impl TokenCache {
+    pub fn new(path: PathBuf) -> TokenCache {
+        TokenCache { path }
+    }
+
+    /**
+     * Get the default cache file in the user cache directory.
+     * Example: ~/.cache/http-client/oauth-tokens.json
+     */
+    pub fn get_default_path() -> Option<PathBuf> {
+        let cache_dir = match std::env::var_os("XDG_CACHE_HOME") {
+            Some(cache_dir) if !cache_dir.is_empty() => PathBuf::from(cache_dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache")
+        };
+        Some(cache_dir.join("http-client").join("oauth-tokens.json"))
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /**
+     * Get the cached token. Returns None if there is no token or the cache cannot be read.
+     */
+    pub fn load(&self, key: &str) -> Option<OAuthToken> {
+        OAuthToken::from_json(self.read().get(key)?)
+    }
+
+    /**
+     * Stores the token, replacing the cached token for the key.
+     */
+    pub fn store(&self, key: &str, token: &OAuthToken) -> Result<(), ClientError> {
+        let mut tokens = self.read();
+        tokens.insert(key.to_string(), token.to_json());
+        self.write(&Value::Object(tokens))
+    }
+
+    fn read(&self) -> Map<String, Value> {
+        match fs::read(&self.path).ok().and_then(|data| serde_json::from_slice(&data).ok()) {
+            Some(Value::Object(tokens)) => tokens,
+            _ => Map::new()
+        }
+    }
+
+    /**
+     * Writes the tokens to a temporary file next to the cache, which then replaces the cache.
+     * The permissions are set on every write, so an existing temporary file readable by others is
+     * not used as is.
+     */
+    fn write(&self, tokens: &Value) -> Result<(), ClientError> {
+        if let Some(parent) = self.path.parent() {
+            if let Err(err) = fs::create_dir_all(parent) {
+                return Err(ClientError::new(ClientErrorType::WriteError, format!("Token cache directory could not be created: {}", err)));
+            }
+        }
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(format!(".{}.tmp", std::process::id()));
+        let temp_path = PathBuf::from(temp_path);
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let result = options.open(&temp_path)
+            .and_then(|mut file| {
+                #[cfg(unix)]
+                file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+                file.write_all(tokens.to_string().as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&temp_path, &self.path));
+        match result {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                let _ = fs::remove_file(&temp_path);
+                Err(ClientError::new(ClientErrorType::WriteError, format!("Token cache {:?} could not be written: {}", self.path, err)))
+            }
+        }
+    }
+}
+
+/**
+ * Authorization request of the authorization code flow with PKCE, as described in RFC 7636.
+ * The url is opened in a browser, which is redirected to the redirect uri with the code.
+ */
+#[derive(Debug)]
+pub struct AuthorizationRequest {
+    pub url: Url,
+    pub redirect_uri: String,
+    state: String,
+    code_verifier: String
+}
+
+/**
+ * OAuth 2.0 authentication with bearer tokens, as described in RFC 6749 and RFC 6750.
+ * A token is requested with the flow of the configuration and cached until it expires. An
+ * expired token, or a token the server responds 401 to, is refreshed with its refresh token,
+ * or requested again if it has none. The token is only sent to its origin.
+ */
+pub struct OAuthAuth {
+    config: OAuthConfig,
+    origin: PoolKey,
+    token_cache: Option<TokenCache>,
+    token: Option<OAuthToken>
+}
+
+impl OAuthAuth {
+    /**
+     * Creates authentication with tokens for the origin.
+     */
+    pub fn new(config: OAuthConfig, origin: PoolKey) -> OAuthAuth {
+        OAuthAuth {
+            token_cache: config.token_cache.clone().map(TokenCache::new),
+            config,
+            origin,
+            token: None
+        }
+    }
+
+    /**
+     * Sends the request to the origin with a bearer token. If the server responds 401, the
+     * token is refreshed and the request sent again.
+     * A request with a streamed body cannot be sent again, so the 401 response is returned.
+     */
+    pub fn send(&mut self, http_client: &mut HttpClient, mut http_request: HttpRequest, origin: &PoolKey) -> Result<HttpResponse, ClientError> {
+        let retry_request = get_retry_request(&http_request);
+        let authorized = self.authorize(&mut http_request, origin)?;
+        let http_response = http_client.send(http_request)?;
+        if !authorized || http_response.response_code != UNAUTHORIZED {
+            return Ok(http_response);
+        }
+        self.expire_token();
+        match retry_request {
+            Some(mut retry_request) => {
+                self.authorize(&mut retry_request, origin)?;
+                http_client.send(retry_request)
+            },
+            None => Ok(http_response)
+        }
+    }
+
+    /**
+     * Adds the Authorization header with a valid token to a request to the origin.
+     * The header is not added if the origin is not the origin of the token, or if the request
+     * already has an Authorization header.
+     *
+     * Returns true if the header was added.
+     */
+    pub fn authorize(&mut self, http_request: &mut HttpRequest, origin: &PoolKey) -> Result<bool, ClientError> {
+        if *origin != self.origin || http_request.headers.contains(AUTHORIZATION) {
+            return Ok(false);
+        }
+        let authorization = self.get_token()?.get_authorization();
+        http_request.headers.append(AUTHORIZATION, &authorization);
+        Ok(true)
+    }
+
+    /**
+     * Get a valid token. The token is loaded from the cache, refreshed if it has expired, or
+     * requested with the flow of the configuration.
+     */
+    pub fn get_token(&mut self) -> Result<&OAuthToken, ClientError> {
+        let now = get_now();
+        if self.token.is_none() {
+            self.token = self.token_cache.as_ref().and_then(|token_cache| token_cache.load(&self.config.get_cache_key()));
+        }
+        let token = match self.token.take() {
+            Some(token) if !token.is_expired(now) => token,
+            expired => match self.get_new_token(expired.as_ref()) {
+                Ok(token) => token,
+                Err(err) => {
+                    self.token = expired;
+                    return Err(err);
+                }
+            }
+        };
+        Ok(self.token.insert(token))
+    }
+
+    /**
+     * Get a new token, refreshed with the refresh token of the expired token or the configuration,
+     * or else requested with the flow of the configuration. The new token is stored in the cache.
+     */
+    fn get_new_token(&self, expired: Option<&OAuthToken>) -> Result<OAuthToken, ClientError> {
+        let refresh_token = expired
+            .and_then(|token| token.refresh_token.clone())
+            .or(self.config.refresh_token.clone());
+        let token = match refresh_token {
+            Some(refresh_token) => match self.refresh(&refresh_token) {
+                Ok(token) => token,
+                Err(err) if self.config.flow == OAuthFlow::RefreshToken => return Err(err),
+                Err(_) => self.request_grant()?
+            },
+            None => self.request_grant()?
+        };
+        if let Some(token_cache) = &self.token_cache {
+            token_cache.store(&self.config.get_cache_key(), &token)?;
+        }
+        Ok(token)
+    }
+
+    /**
+     * Marks the token as expired, so it is refreshed before it is used again.
+     */
+    pub fn expire_token(&mut self) {
+        if let Some(token) = &mut self.token {
+            token.expires_at = Some(0);
+        }
+    }
+
+    /**
+     * Get the authorization request for the authorization code flow, with a new state and code
+     * verifier, redirecting to the loopback listener on the port.
+     */
+    pub fn get_authorization_request(&self, port: u16) -> Result<AuthorizationRequest, ClientError> {
+        let mut url = match &self.config.authorization_url {
+            Some(url) => url.clone(),
+            None => return Err(ClientError::new(ClientErrorType::InvalidRequest, "The authorization code flow requires an authorization url".to_string()))
+        };
+        let redirect_uri = format!("http://127.0.0.1:{}{}", port, CALLBACK_PATH);
+        let state = get_random_string(STATE_LENGTH)?;
+        let code_verifier = get_random_string(VERIFIER_LENGTH)?;
+        let mut params = vec![
+            ("response_type", "code".to_string()),
+            ("client_id", self.config.client_id.clone()),
+            ("redirect_uri", redirect_uri.clone()),
+            ("state", state.clone()),
+            ("code_challenge", get_code_challenge(&code_verifier)),
+            ("code_challenge_method", "S256".to_string())
+        ];
+        if let Some(scope) = &self.config.scope {
+            params.push(("scope", scope.clone()));
+        }
+        let query = match url.query() {
+            Some(query) if !query.is_empty() => format!("{}&{}", query, encode_form(&params)),
+            _ => encode_form(&params)
+        };
+        url.set_query(Some(&query));
+        Ok(AuthorizationRequest { url, redirect_uri, state, code_verifier })
+    }
+
+    /**
+     * Requests a token with the grant of the flow.
+     */
+    fn request_grant(&self) -> Result<OAuthToken, ClientError> {
+        match self.config.flow {
+            OAuthFlow::ClientCredentials => {
+                let mut params = vec![("grant_type", "client_credentials".to_string())];
+                if let Some(scope) = &self.config.scope {
+                    params.push(("scope", scope.clone()));
+                }
+                self.request_token(params)
+            },
+            OAuthFlow::AuthorizationCode => self.request_authorization_code(),
+            OAuthFlow::RefreshToken => Err(ClientError::new(ClientErrorType::AuthorizationFailed, "The refresh token flow requires a refresh token".to_string()))
+        }
+    }
+
+    /**
+     * Refreshes the token as described in RFC 6749 section 6.
+     * The refresh token is kept if the server does not issue a new one.
+     */
+    fn refresh(&self, refresh_token: &str) -> Result<OAuthToken, ClientError> {
+        let mut params = vec![("grant_type", "refresh_token".to_string()), ("refresh_token", refresh_token.to_string())];
+        if let Some(scope) = &self.config.scope {
+            params.push(("scope", scope.clone()));
+        }
+        let mut token = self.request_token(params)?;
+        if token.refresh_token.is_none() {
+            token.refresh_token = Some(refresh_token.to_string());
+        }
+        Ok(token)
+    }
+
+    /**
+     * Runs the authorization code flow. The authorization url is passed to the handler of the
+     * configuration to be opened in a browser, and the code is received on a loopback listener, as described in RFC 8252 section 7.3.
+     */
+    fn request_authorization_code(&self) -> Result<OAuthToken, ClientError> {
+        let authorization_url_handler = match self.config.authorization_url_handler {
+            Some(authorization_url_handler) => authorization_url_handler,
+            None => return Err(ClientError::new(ClientErrorType::InvalidRequest, "The authorization code flow requires an authorization url handler".to_string()))
+        };
+        let listener = match TcpListener::bind(("127.0.0.1", self.config.redirect_port)) {
+            Ok(listener) => listener,
+            Err(err) => return Err(ClientError::new(ClientErrorType::ConnectionFailure, format!("Loopback listener could not be started: {}", err)))
+        };
+        let port = match listener.local_addr() {
+            Ok(address) => address.port(),
+            Err(err) => return Err(ClientError::new(ClientErrorType::ConnectionFailure, format!("Loopback listener has no address: {}", err)))
+        };
+        let authorization_request = self.get_authorization_request(port)?;
+        authorization_url_handler(&authorization_request.url);
+        let code = wait_for_redirect(&listener, &authorization_request.state, REDIRECT_TIMEOUT)?;
+        self.request_token(vec![
+            ("grant_type", "authorization_code".to_string()),
+            ("code", code),
+            ("redirect_uri", authorization_request.redirect_uri),
+            ("code_verifier", authorization_request.code_verifier)
+        ])
+    }
+
+    /**
+     * Sends a token request to the token endpoint, as described in RFC 6749 section 4.1.3 and 4.4.2.
+     */
+    fn request_token(&self, mut params: Vec<(&str, String)>) -> Result<OAuthToken, ClientError> {
+        let mut headers = HeaderMap::new();
+        headers.append(CONTENT_TYPE, FORM_CONTENT_TYPE);
+        headers.append(ACCEPT, JSON_CONTENT_TYPE);
+        match &self.config.client_secret {
+            Some(client_secret) => {
+                let credentials = format!("{}:{}", urlencode::encode_with(&self.config.client_id, EncodeSet::Form), urlencode::encode_with(client_secret, EncodeSet::Form));
+                headers.append(AUTHORIZATION, &format!("Basic {}", base64::STANDARD.encode(credentials.as_bytes())));
+            },
+            None => params.push(("client_id", self.config.client_id.clone()))
+        }
+        let token_url = &self.config.token_url;
+        let mut path = token_url.path().to_string();
+        if let Some(query) = token_url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        let http_request = HttpRequest::new(path, "POST".to_string(), get_authority(token_url), headers, Some(RequestBody::from_string(encode_form(&params))));
+        let mut http_client = get_http_client(token_url, self.config.connection_timeout, &self.config.tls_settings)?;
+        let http_response = http_client.send(http_request)?;
+        if http_response.response_code != 200 {
+            return Err(get_token_error(&http_response));
+        }
+        OAuthToken::from_response(&http_response.body, get_now())
+    }
+}
+
+impl fmt::Debug for OAuthAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OAuthAuth")
+            .field("config", &self.config)
+            .field("origin", &self.origin)
+            .field("token", &self.token)
+            .finish()
+    }
+}
+
+/**
+ * Waits for the browser to be redirected to the loopback listener and returns the code.
+ * Requests to other paths are answered with 404 and requests with a malformed query with 400,
+ * and the wait goes on. Returns an error if the state does not match, the authorization server
+ * returned an error, or no redirect is received before the timeout.
+ */
+fn wait_for_redirect(listener: &TcpListener, state: &str, timeout: Duration) -> Result<String, ClientError> {
+    if let Err(err) = listener.set_nonblocking(true) {
+        return Err(ClientError::new(ClientErrorType::ConnectionFailure, format!("Loopback listener could not be configured: {}", err)));
+    }
+    let deadline = Instant::now() + timeout;
+    loop {
+        let mut stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(ClientError::new(ClientErrorType::AuthorizationFailed, "Timed out waiting for the authorization redirect".to_string()));
+                }
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            },
+            Err(err) => return Err(ClientError::new(ClientErrorType::ConnectionFailure, format!("Loopback listener failed: {}", err)))
+        };
+        let target = match read_request_target(&stream) {
+            Some(target) => target,
+            None => continue
+        };
+        let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+        if path != CALLBACK_PATH {
+            write_page(&mut stream, "404 Not Found", "Not found.");
+            continue;
+        }
+        let params = match decode_form(query) {
+            Ok(params) => params,
+            Err(_) => {
+                write_page(&mut stream, "400 Bad Request", "Authorization failed, the query is malformed.");
+                continue;
+            }
+        };
+        let get_param = |name: &str| params.iter().find(|param| param.0 == name).map(|param| param.1.clone());
+        if get_param("state").as_deref() != Some(state) {
+            write_page(&mut stream, "400 Bad Request", "Authorization failed, the state does not match.");
+            return Err(ClientError::new(ClientErrorType::AuthorizationFailed, "State of the authorization redirect does not match".to_string()));
+        }
+        if let Some(error) = get_param("error") {
+            write_page(&mut stream, "400 Bad Request", "Authorization failed.");
+            let description = get_param("error_description").unwrap_or_default();
+            return Err(ClientError::new(ClientErrorType::AuthorizationFailed, format!("Authorization failed: {} {}", error, description).trim_end().to_string()));
+        }
+        match get_param("code") {
+            Some(code) => {
+                write_page(&mut stream, "200 OK", "Authorization complete, you can close this window.");
+                return Ok(code);
+            },
+            None => {
+                write_page(&mut stream, "400 Bad Request", "Authorization failed, no code received.");
+                return Err(ClientError::new(ClientErrorType::AuthorizationFailed, "Authorization redirect without code".to_string()));
+            }
+        }
+    }
+}
+
+/**
+ * Reads the request head from the browser and returns the request target.
+ */
+fn read_request_target(stream: &TcpStream) -> Option<String> {
+    stream.set_nonblocking(false).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut line = String::new();
+    while reader.read_line(&mut line).is_ok_and(|length| length > 2) {
+        line.clear();
+    }
+    request_line.split(' ').nth(1).map(str::to_string)
+}
+
+fn write_page(stream: &mut TcpStream, status: &str, message: &str) {
+    let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, message.len(), message);
+}
+
+/**
+ * Get the error of a failed token request, as described in RFC 6749 section 5.2.
+ */
+fn get_token_error(http_response: &HttpResponse) -> ClientError {
+    let value: Value = serde_json::from_slice(&http_response.body).unwrap_or_default();
+    let message = match (value["error"].as_str(), value["error_description"].as_str()) {
+        (Some(error), Some(description)) => format!("Token request failed with {}: {} {}", http_response.response_code, error, description),
+        (Some(error), None) => format!("Token request failed with {}: {}", http_response.response_code, error),
+        _ => format!("Token request failed with {} {}", http_response.response_code, http_response.reason)
+    };
+    ClientError::new(ClientErrorType::AuthorizationFailed, message)
+}
+
+/**
+ * Get a client for the token endpoint. The host is taken as written in the url, so an IPv6
+ * address keeps its brackets.
+ */
+fn get_http_client(url: &Url, connection_timeout: Duration, tls_settings: &TlsSettings) -> Result<HttpClient, ClientError> {
+    let origin = PoolKey::from_url(url)?;
+    let host = url.host_str().unwrap_or_default().to_string();
+    if origin.scheme == "https" {
+        let client_config = TlsConnection::get_client_config_with(tls_settings)?;
+        Ok(HttpClient::new_tls(host.clone(), origin.port, connection_timeout, host, client_config))
+    } else {
+        Ok(HttpClient::new(host, origin.port, connection_timeout))
+    }
+}
+
+/**
+ * Get the authority to send in the Host header, the port is only included if it is in the url.
+ */
+fn get_authority(url: &Url) -> String {
+    match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string()
+    }
+}
+
+fn encode_form(params: &[(&str, String)]) -> String {
+    params.iter()
+        .map(|(name, value)| format!("{}={}", urlencode::encode_with(name, EncodeSet::Form), urlencode::encode_with(value, EncodeSet::Form)))
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+fn decode_form(query: &str) -> Result<Vec<(String, String)>, ClientError> {
+    let mut params: Vec<(String, String)> = Vec::new();
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        params.push((urlencode::decode_form(name)?, urlencode::decode_form(value)?));
+    }
+    Ok(params)
+}
+
+/**
+ * Get the S256 code challenge of the code verifier, as described in RFC 7636 section 4.2.
+ */
+fn get_code_challenge(code_verifier: &str) -> String {
+    base64::URL_SAFE_NO_PAD.encode(&Sha256::digest(code_verifier.as_bytes()))
+}
+
+/**
+ * Get random bytes encoded as url safe base64, for the state and code verifier.
+ */
+fn get_random_string(length: usize) -> Result<String, ClientError> {
+    let mut bytes = vec![0u8; length];
+    fill_random(&mut bytes)?;
+    Ok(base64::URL_SAFE_NO_PAD.encode(&bytes))
+}
+
+fn get_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    /**
+     * Starts a server answering each connection with the next response.
+     * Returns the port and a handle returning the requests received.
+     */
+    fn start_server(responses: Vec<String>) -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let mut requests: Vec<String> = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(length) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                    request.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0u8; content_length];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8(body).unwrap());
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+        (port, handle)
+    }
+
+    fn get_response(status: &str, body: &str) -> String {
+        format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body)
+    }
+
+    fn get_config(flow: OAuthFlow, port: u16) -> OAuthConfig {
+        OAuthConfig::new(flow, Url::parse(&format!("http://127.0.0.1:{}/token", port)).unwrap(), "client".to_string(), Duration::from_secs(2))
+    }
+
+    fn get_request() -> HttpRequest {
+        HttpRequest::new("/".to_string(), "GET".to_string(), "127.0.0.1".to_string(), HeaderMap::new(), None)
+    }
+
+    fn get_cache_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("http-client-test-{}-{}", std::process::id(), name)).join("tokens.json");
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_token_from_response() {
+        let token = OAuthToken::from_response(b"{\"access_token\":\"abc\",\"token_type\":\"bearer\",\"expires_in\":3600,\"refresh_token\":\"r\",\"scope\":\"read\"}", 1000).unwrap();
+        assert_eq!(token.get_authorization(), "Bearer abc");
+        assert_eq!(token.expires_at, Some(4600));
+        assert_eq!(token.refresh_token, Some("r".to_string()));
+        assert!(!token.is_expired(4000));
+        assert!(token.is_expired(4580));
+        let token = OAuthToken::from_response(b"{\"access_token\":\"abc\",\"token_type\":\"Bearer\",\"expires_in\":\"60\"}", 0).unwrap();
+        assert_eq!(token.expires_at, Some(60));
+        let error = OAuthToken::from_response(b"{\"access_token\":\"abc\",\"token_type\":\"mac\"}", 0).err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::Unimplemented);
+        let error = OAuthToken::from_response(b"{\"token_type\":\"bearer\"}", 0).err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::AuthorizationFailed);
+    }
+
+    #[test]
+    fn test_code_challenge() {
+        assert_eq!(get_code_challenge("dBjftJeZ4CVP-mJ92K1dc1b5RRfbfcfDoZUWhN0JyZ0"), "Cgi_pRbPP5NLk_JR6d6pZ5wA-IcV3QqNTn3ZhtJbdLQ");
+    }
+
+    #[test]
+    fn test_client_credentials() {
+        let (port, handle) = start_server(vec![get_response("200 OK", "{\"access_token\":\"abc\",\"token_type\":\"Bearer\",\"expires_in\":3600}")]);
+        let config = get_config(OAuthFlow::ClientCredentials, port).with_client_secret(Some("se cret".to_string())).with_scope(Some("read write".to_string()));
+        let origin = PoolKey::new("http", "127.0.0.1", 80);
+        let mut oauth = OAuthAuth::new(config, origin.clone());
+        let mut http_request = get_request();
+        assert!(oauth.authorize(&mut http_request, &origin).unwrap());
+        assert_eq!(http_request.headers.get("authorization"), Some("Bearer abc"));
+        let mut http_request = get_request();
+        assert!(oauth.authorize(&mut http_request, &origin).unwrap());
+        let requests = handle.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("POST /token HTTP/1.1\r\n"));
+        assert!(requests[0].contains(&format!("Authorization: Basic {}\r\n", base64::STANDARD.encode(b"client:se+cret"))));
+        assert!(requests[0].ends_with("\r\n\r\ngrant_type=client_credentials&scope=read+write"));
+    }
+
+    #[test]
+    fn test_authorize_only_same_origin() {
+        let mut oauth = OAuthAuth::new(get_config(OAuthFlow::ClientCredentials, 1), PoolKey::new("https", "api.example.com", 443));
+        let mut http_request = get_request();
+        assert!(!oauth.authorize(&mut http_request, &PoolKey::new("https", "example.com", 443)).unwrap());
+        assert!(!http_request.headers.contains("authorization"));
+    }
+
+    #[test]
+    fn test_refresh_expired_cached_token() {
+        let path = get_cache_path("refresh");
+        let (port, handle) = start_server(vec![get_response("200 OK", "{\"access_token\":\"new\",\"token_type\":\"Bearer\",\"expires_in\":3600}")]);
+        let config = get_config(OAuthFlow::ClientCredentials, port).with_token_cache(Some(path.clone()));
+        let expired = OAuthToken::from_response(b"{\"access_token\":\"old\",\"token_type\":\"Bearer\",\"expires_in\":10,\"refresh_token\":\"r1\"}", 0).unwrap();
+        TokenCache::new(path.clone()).store(&config.get_cache_key(), &expired).unwrap();
+        let mut oauth = OAuthAuth::new(config.clone(), PoolKey::new("http", "127.0.0.1", 80));
+        assert_eq!(oauth.get_token().unwrap().get_authorization(), "Bearer new");
+        let requests = handle.join().unwrap();
+        assert!(requests[0].ends_with("grant_type=refresh_token&refresh_token=r1&client_id=client"));
+        let cached = TokenCache::new(path.clone()).load(&config.get_cache_key()).unwrap();
+        assert_eq!(cached.access_token, "new");
+        assert_eq!(cached.refresh_token, Some("r1".to_string()));
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&path).unwrap().permissions()) & 0o777, 0o600);
+        let mut oauth = OAuthAuth::new(config, PoolKey::new("http", "127.0.0.1", 80));
+        assert_eq!(oauth.get_token().unwrap().get_authorization(), "Bearer new");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_token_cache_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = get_cache_path("permissions");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "{}").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let token = OAuthToken::from_response(b"{\"access_token\":\"abc\",\"token_type\":\"Bearer\"}", 0).unwrap();
+        TokenCache::new(path.clone()).store("key", &token).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(TokenCache::new(path.clone()).load("key").unwrap().access_token, "abc");
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_refresh_failure_falls_back_to_grant() {
+        let (port, handle) = start_server(vec![
+            get_response("400 Bad Request", "{\"error\":\"invalid_grant\"}"),
+            get_response("200 OK", "{\"access_token\":\"abc\",\"token_type\":\"Bearer\"}")
+        ]);
+        let config = get_config(OAuthFlow::ClientCredentials, port).with_refresh_token(Some("revoked".to_string()));
+        let mut oauth = OAuthAuth::new(config, PoolKey::new("http", "127.0.0.1", 80));
+        assert_eq!(oauth.get_token().unwrap().get_authorization(), "Bearer abc");
+        let requests = handle.join().unwrap();
+        assert!(requests[1].contains("grant_type=client_credentials"));
+        let (port, _handle) = start_server(vec![get_response("400 Bad Request", "{\"error\":\"invalid_grant\",\"error_description\":\"revoked\"}")]);
+        let config = get_config(OAuthFlow::RefreshToken, port).with_refresh_token(Some("revoked".to_string()));
+        let error = OAuthAuth::new(config, PoolKey::new("http", "127.0.0.1", 80)).get_token().err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::AuthorizationFailed);
+        assert_eq!(error.message, "Token request failed with 400: invalid_grant revoked");
+    }
+
+    #[test]
+    fn test_send_refreshes_on_unauthorized() {
+        let (token_port, token_handle) = start_server(vec![
+            get_response("200 OK", "{\"access_token\":\"first\",\"token_type\":\"Bearer\",\"refresh_token\":\"r\"}"),
+            get_response("200 OK", "{\"access_token\":\"second\",\"token_type\":\"Bearer\"}")
+        ]);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_port = listener.local_addr().unwrap().port();
+        let api_handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut authorizations: Vec<String> = Vec::new();
+            for response in ["HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n", "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"] {
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(authorization) = line.strip_prefix("Authorization: ") {
+                        authorizations.push(authorization.trim_end().to_string());
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            authorizations
+        });
+        let origin = PoolKey::new("http", "127.0.0.1", api_port);
+        let mut oauth = OAuthAuth::new(get_config(OAuthFlow::ClientCredentials, token_port), origin.clone());
+        let mut http_client = HttpClient::new("127.0.0.1".to_string(), api_port, Duration::from_secs(2));
+        let http_response = oauth.send(&mut http_client, get_request(), &origin).unwrap();
+        assert_eq!(http_response.response_code, 200);
+        assert_eq!(api_handle.join().unwrap(), vec!["Bearer first", "Bearer second"]);
+        assert!(token_handle.join().unwrap()[1].contains("grant_type=refresh_token&refresh_token=r"));
+    }
+
+    #[test]
+    fn test_authorization_request() {
+        let config = get_config(OAuthFlow::AuthorizationCode, 1)
+            .with_authorization_url(Some(Url::parse("https://auth.example.com/authorize?audience=api").unwrap()))
+            .with_scope(Some("openid profile".to_string()));
+        let oauth = OAuthAuth::new(config, PoolKey::new("http", "127.0.0.1", 80));
+        let authorization_request = oauth.get_authorization_request(8123).unwrap();
+        assert_eq!(authorization_request.redirect_uri, "http://127.0.0.1:8123/callback");
+        assert_eq!(authorization_request.code_verifier.len(), 43);
+        let params = decode_form(authorization_request.url.query().unwrap()).unwrap();
+        let get_param = |name: &str| params.iter().find(|param| param.0 == name).map(|param| param.1.as_str());
+        assert_eq!(get_param("audience"), Some("api"));
+        assert_eq!(get_param("response_type"), Some("code"));
+        assert_eq!(get_param("redirect_uri"), Some("http://127.0.0.1:8123/callback"));
+        assert_eq!(get_param("scope"), Some("openid profile"));
+        assert_eq!(get_param("state"), Some(authorization_request.state.as_str()));
+        assert_eq!(get_param("code_challenge"), Some(get_code_challenge(&authorization_request.code_verifier).as_str()));
+        assert_eq!(get_param("code_challenge_method"), Some("S256"));
+    }
+
+    #[test]
+    fn test_wait_for_redirect() {
+        // The request is sent before the response is read in a thread, so the connections are
+        // accepted in the order they were made.
+        let send = |port: u16, target: &str| {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", target).unwrap();
+            thread::spawn(move || {
+                let mut response = String::new();
+                let _ = stream.read_to_string(&mut response);
+                response
+            })
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let favicon = send(port, "/favicon.ico");
+        let malformed = send(port, "/callback?code=%zz&state=xyz");
+        let redirect = send(port, "/callback?code=a%2Fb&state=xyz");
+        assert_eq!(wait_for_redirect(&listener, "xyz", Duration::from_secs(5)).unwrap(), "a/b");
+        assert!(favicon.join().unwrap().starts_with("HTTP/1.1 404"));
+        assert!(malformed.join().unwrap().starts_with("HTTP/1.1 400"));
+        assert!(redirect.join().unwrap().starts_with("HTTP/1.1 200"));
+        let error = send(port, "/callback?code=c&state=other");
+        assert_eq!(wait_for_redirect(&listener, "xyz", Duration::from_secs(5)).err().unwrap().error_type, ClientErrorType::AuthorizationFailed);
+        assert!(error.join().unwrap().starts_with("HTTP/1.1 400"));
+        let denied = send(port, "/callback?error=access_denied&state=xyz");
+        assert_eq!(wait_for_redirect(&listener, "xyz", Duration::from_secs(5)).err().unwrap().message, "Authorization failed: access_denied");
+        denied.join().unwrap();
+        assert!(wait_for_redirect(&listener, "xyz", Duration::from_millis(200)).is_err());
+    }
+}
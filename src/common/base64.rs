@@ -1,76 +1,316 @@
-use std::cmp::max;
-
-const BASE64_TABLE: [char; 64] = [
-    'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H',
-    'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P',
-    'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X',
-    'Y', 'Z', 'a', 'b', 'c', 'd', 'e', 'f',
-    'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n',
-    'o', 'p', 'q', 'r', 's', 't', 'u', 'v',
-    'w', 'x', 'y', 'z', '0', '1', '2', '3',
-    '4', '5', '6', '7', '8', '9', '+', '/'
-];
-
-pub fn base64_encode(str: &str) -> String {
-    let mut base64_encoded = String::new();
-    let mut current_bits_stored: u8 = 0;
-    let mut bit_storage: u8 = 0;
-    for byte_val in str.as_bytes().iter() { 
-        let new_bits_push: u8 = 6 - current_bits_stored;
-
-        bit_storage <<= new_bits_push;
-        bit_storage ^= byte_val>>(8 - new_bits_push);
-        bit_storage = 0b00111111 & bit_storage;
-        // Pushing first new_bits_push bits
-        current_bits_stored += new_bits_push;
-
-        // Get base64 encoded value
-        base64_encoded.push(BASE64_TABLE[bit_storage as usize]);                  
-        current_bits_stored -= 6;
-        let remaining_bits: u8 = 8 - new_bits_push;
-        // Push remaining bits
-        current_bits_stored += remaining_bits;
-        
-        bit_storage <<= new_bits_push;
-        bit_storage = (byte_val<<new_bits_push)>>new_bits_push;
-        bit_storage = 0b00111111 & bit_storage;
-
-        if current_bits_stored >= 6 {
-            base64_encoded.push(BASE64_TABLE[bit_storage as usize]);                  
-
-            bit_storage = 0;
-            current_bits_stored = 0;
+use std::fmt;
+
+use crate::common::{ ClientError, ClientErrorType };
+
+const STANDARD_TABLE: [u8; 64] = *b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE_TABLE: [u8; 64] = *b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const PADDING: u8 = b'=';
+
+/**
+ * Base64 with the standard alphabet and padding, as described in RFC 4648 section 4.
+ * Used by Basic authentication and data URIs.
+ */
+pub const STANDARD: Base64 = Base64 { alphabet: Alphabet::Standard, padding: true };
+
+/**
+ * Base64 with the standard alphabet without padding.
+ */
+pub const STANDARD_NO_PAD: Base64 = Base64 { alphabet: Alphabet::Standard, padding: false };
+
+/**
+ * Base64 with the URL and filename safe alphabet and padding, as described in RFC 4648 section 5.
+ */
+pub const URL_SAFE: Base64 = Base64 { alphabet: Alphabet::UrlSafe, padding: true };
+
+/**
+ * Base64 with the URL and filename safe alphabet without padding. Used by JWT.
+ */
+pub const URL_SAFE_NO_PAD: Base64 = Base64 { alphabet: Alphabet::UrlSafe, padding: false };
+
+/**
+ * Base64 alphabet.
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Alphabet {
+    // A-Z a-z 0-9 + /
+    Standard,
+    // A-Z a-z 0-9 - _
+    UrlSafe,
+}
+
+impl Alphabet {
+    fn get_table(&self) -> &'static [u8; 64] {
+        match self {
+            Alphabet::Standard => &STANDARD_TABLE,
+            Alphabet::UrlSafe => &URL_SAFE_TABLE,
+        }
+    }
+
+    /**
+     * Get the value of a character in the alphabet.
+     * If lenient is true, characters of both alphabets are accepted.
+     */
+    fn get_value(&self, character: u8, lenient: bool) -> Option<u8> {
+        match character {
+            b'A'..=b'Z' => Some(character - b'A'),
+            b'a'..=b'z' => Some(character - b'a' + 26),
+            b'0'..=b'9' => Some(character - b'0' + 52),
+            b'+' if lenient || *self == Alphabet::Standard => Some(62),
+            b'/' if lenient || *self == Alphabet::Standard => Some(63),
+            b'-' if lenient || *self == Alphabet::UrlSafe => Some(62),
+            b'_' if lenient || *self == Alphabet::UrlSafe => Some(63),
+            _ => None
+        }
+    }
+}
+
+/**
+ * Type of base64 decoding error.
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DecodeErrorType {
+    // A character is not in the alphabet.
+    InvalidCharacter,
+    // The number of characters cannot be produced by encoding.
+    InvalidLength,
+    // Padding is missing, not expected or not at the end.
+    InvalidPadding,
+    // The unused bits of the last character are not zero.
+    NonZeroTrailingBits,
+}
+
+/**
+ * Base64 decoding error. The error contains the type and a message with the reason.
+ */
+#[derive(Debug, PartialEq)]
+pub struct DecodeError {
+    pub error_type: DecodeErrorType,
+    pub message: String
+}
+
+impl DecodeError {
+    pub fn new(error_type: DecodeErrorType, message: String) -> DecodeError {
+        DecodeError {
+            error_type,
+            message
         }
-    };
-    // Add remaining
-    let padding = str.as_bytes().len() * 8;
-    if (padding % 6) != 0 {
-        bit_storage = bit_storage << (6 - current_bits_stored);
-        base64_encoded.push(BASE64_TABLE[bit_storage as usize]);
     }
-    //Add padding
-    for i in 0..((str.as_bytes().len() * 8) % 3) {
-        base64_encoded.push('=');
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
     }
-    
-    base64_encoded               
+}
+
+impl std::error::Error for DecodeError {}
 
+impl From<DecodeError> for ClientError {
+    fn from(err: DecodeError) -> ClientError {
+        ClientError::new(ClientErrorType::InvalidBase64, err.message)
+    }
+}
+
+/**
+ * Base64 encoding with an alphabet and optional padding.
+ * Example: base64::STANDARD.encode(b"user:password")
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Base64 {
+    pub alphabet: Alphabet,
+    pub padding: bool
+}
+
+impl Base64 {
+    /**
+     * Encodes bytes to a base64 string.
+     */
+    pub fn encode(&self, input: &[u8]) -> String {
+        let table = self.alphabet.get_table();
+        let mut result: Vec<u8> = Vec::with_capacity(input.len().div_ceil(3) * 4);
+        for chunk in input.chunks(3) {
+            let bits = (chunk[0] as u32) << 16
+                | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+                | *chunk.get(2).unwrap_or(&0) as u32;
+            let characters = chunk.len() + 1;
+            for index in 0..characters {
+                result.push(table[(bits >> (18 - index * 6) & 0b111111) as usize]);
+            }
+            if self.padding {
+                result.extend(std::iter::repeat_n(PADDING, 4 - characters));
+            }
+        }
+        String::from_utf8(result).unwrap_or_default()
+    }
+
+    /**
+     * Decodes a base64 string in strict mode.
+     * Only characters of the alphabet are accepted, padding must be present if and only if
+     * the encoding uses padding, and the unused bits of the last character must be zero,
+     * so every encoded value has exactly one accepted representation.
+     */
+    pub fn decode(&self, input: &str) -> Result<Vec<u8>, DecodeError> {
+        let input = input.as_bytes();
+        let padding_length = input.iter().rev().take_while(|character| **character == PADDING).count();
+        if self.padding {
+            if !input.len().is_multiple_of(4) {
+                return Err(DecodeError::new(DecodeErrorType::InvalidLength, format!("Length {} is not a multiple of 4", input.len())));
+            }
+            if padding_length > 2 {
+                return Err(DecodeError::new(DecodeErrorType::InvalidPadding, "Too much padding".to_string()));
+            }
+        } else if padding_length > 0 {
+            return Err(DecodeError::new(DecodeErrorType::InvalidPadding, "Padding is not expected".to_string()));
+        }
+        self.decode_symbols(&input[..input.len() - padding_length], false)
+    }
+
+    /**
+     * Decodes a base64 string in lenient mode.
+     * ASCII whitespace is ignored, characters of both alphabets are accepted, padding is
+     * optional and the unused bits of the last character are ignored.
+     */
+    pub fn decode_lenient(&self, input: &str) -> Result<Vec<u8>, DecodeError> {
+        let input: Vec<u8> = input.bytes().filter(|character| !character.is_ascii_whitespace()).collect();
+        let padding_length = input.iter().rev().take_while(|character| **character == PADDING).count();
+        let symbols = &input[..input.len() - padding_length];
+        if padding_length > 0 && (padding_length > 2 || !(symbols.len() + padding_length).is_multiple_of(4)) {
+            return Err(DecodeError::new(DecodeErrorType::InvalidPadding, "Padding does not match the length".to_string()));
+        }
+        self.decode_symbols(symbols, true)
+    }
+
+    /**
+     * Decodes base64 characters without padding.
+     */
+    fn decode_symbols(&self, symbols: &[u8], lenient: bool) -> Result<Vec<u8>, DecodeError> {
+        if symbols.len() % 4 == 1 {
+            return Err(DecodeError::new(DecodeErrorType::InvalidLength, format!("Invalid length {}", symbols.len())));
+        }
+        let mut result: Vec<u8> = Vec::with_capacity(symbols.len() / 4 * 3 + 2);
+        for (chunk_index, chunk) in symbols.chunks(4).enumerate() {
+            let mut bits: u32 = 0;
+            for (index, character) in chunk.iter().enumerate() {
+                let value = match self.alphabet.get_value(*character, lenient) {
+                    Some(value) => value,
+                    None if *character == PADDING => return Err(DecodeError::new(DecodeErrorType::InvalidPadding, format!("Padding at position {} is not at the end", chunk_index * 4 + index))),
+                    None => return Err(DecodeError::new(DecodeErrorType::InvalidCharacter, format!("Invalid character {:?} at position {}", *character as char, chunk_index * 4 + index)))
+                };
+                bits |= (value as u32) << (18 - index * 6);
+            }
+            let bytes = chunk.len() - 1;
+            if !lenient && bits << (bytes * 8) & 0xFFFFFF != 0 {
+                return Err(DecodeError::new(DecodeErrorType::NonZeroTrailingBits, "Unused bits of the last character are not zero".to_string()));
+            }
+            result.extend(&bits.to_be_bytes()[1..=bytes]);
+        }
+        Ok(result)
+    }
 }
 
 #[cfg(test)]
 mod test {
- 
     use super::*;
+    use proptest::prelude::*;
+
+    const ENCODINGS: [Base64; 4] = [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD];
 
     #[test]
-    fn test_base64_encode() {
-        assert_eq!("VGVzdGluZzEwMA==", base64_encode("Testing100"));
-        assert_eq!("VGVzdGluZzEw", base64_encode("Testing10"));
-        assert_eq!("VGVzdGluZzE=", base64_encode("Testing1"));                   
-        assert_eq!("VGVzdGluZw==", base64_encode("Testing"));
-        assert_eq!("VGVzdGlu", base64_encode("Testin"));
-        assert_eq!("", base64_encode(""));
-        assert_eq!("VGhpcyBpcyBhIHZlcnkgbG9uZyBsaW5lLg==", base64_encode("This is a very long line."));
+    fn test_encode() {
+        assert_eq!("VGVzdGluZzEwMA==", STANDARD.encode(b"Testing100"));
+        assert_eq!("VGVzdGluZzEw", STANDARD.encode(b"Testing10"));
+        assert_eq!("VGVzdGluZzE=", STANDARD.encode(b"Testing1"));
+        assert_eq!("VGVzdGluZw==", STANDARD.encode(b"Testing"));
+        assert_eq!("VGVzdGlu", STANDARD.encode(b"Testin"));
+        assert_eq!("", STANDARD.encode(b""));
+        assert_eq!("VGhpcyBpcyBhIHZlcnkgbG9uZyBsaW5lLg==", STANDARD.encode(b"This is a very long line."));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_encode_rfc4648_vectors() {
+        let vectors = [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foob", "Zm9vYg=="), ("fooba", "Zm9vYmE="), ("foobar", "Zm9vYmFy")];
+        for (input, output) in vectors {
+            assert_eq!(STANDARD.encode(input.as_bytes()), output);
+            assert_eq!(STANDARD.decode(output).unwrap(), input.as_bytes());
+            assert_eq!(STANDARD_NO_PAD.encode(input.as_bytes()), output.trim_end_matches('='));
+            assert_eq!(STANDARD_NO_PAD.decode(output.trim_end_matches('=')).unwrap(), input.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_encode_url_safe() {
+        assert_eq!(STANDARD.encode(&[0xFB, 0xFF, 0xBF]), "+/+/");
+        assert_eq!(URL_SAFE.encode(&[0xFB, 0xFF, 0xBF]), "-_-_");
+        assert_eq!(URL_SAFE.encode(&[0xFF]), "_w==");
+        assert_eq!(URL_SAFE_NO_PAD.encode(&[0xFF]), "_w");
+        assert_eq!(URL_SAFE_NO_PAD.decode("-_-_").unwrap(), vec![0xFB, 0xFF, 0xBF]);
+    }
+
+    #[test]
+    fn test_round_trip_all_one_and_two_byte_inputs() {
+        for encoding in ENCODINGS {
+            for first in 0..=255u8 {
+                assert_eq!(encoding.decode(&encoding.encode(&[first])).unwrap(), vec![first]);
+                for second in 0..=255u8 {
+                    assert_eq!(encoding.decode(&encoding.encode(&[first, second])).unwrap(), vec![first, second]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_strict_errors() {
+        assert_eq!(STANDARD.decode("Zg=").err().unwrap().error_type, DecodeErrorType::InvalidLength);
+        assert_eq!(STANDARD.decode("Zg").err().unwrap().error_type, DecodeErrorType::InvalidLength);
+        assert_eq!(STANDARD.decode("Z===").err().unwrap().error_type, DecodeErrorType::InvalidPadding);
+        assert_eq!(STANDARD.decode("Zg==Zg==").err().unwrap().error_type, DecodeErrorType::InvalidPadding);
+        assert_eq!(STANDARD.decode("Zh==").err().unwrap().error_type, DecodeErrorType::NonZeroTrailingBits);
+        assert_eq!(STANDARD.decode("Zm9=").err().unwrap().error_type, DecodeErrorType::NonZeroTrailingBits);
+        assert_eq!(STANDARD.decode("Zm9v\n").err().unwrap().error_type, DecodeErrorType::InvalidLength);
+        assert_eq!(STANDARD.decode("Zm 9").err().unwrap().error_type, DecodeErrorType::InvalidCharacter);
+        assert_eq!(STANDARD.decode("-_-_").err().unwrap().error_type, DecodeErrorType::InvalidCharacter);
+        assert_eq!(URL_SAFE.decode("+/+/").err().unwrap().error_type, DecodeErrorType::InvalidCharacter);
+        assert_eq!(STANDARD_NO_PAD.decode("Zg==").err().unwrap().error_type, DecodeErrorType::InvalidPadding);
+        assert_eq!(STANDARD_NO_PAD.decode("Zm9vY").err().unwrap().error_type, DecodeErrorType::InvalidLength);
+    }
+
+    #[test]
+    fn test_decode_lenient() {
+        assert_eq!(STANDARD.decode_lenient("Zm9v\r\nYmFy").unwrap(), b"foobar");
+        assert_eq!(STANDARD.decode_lenient(" Zm9vYg ").unwrap(), b"foob");
+        assert_eq!(STANDARD.decode_lenient("Zm9vYg==").unwrap(), b"foob");
+        assert_eq!(STANDARD.decode_lenient("-_-_").unwrap(), vec![0xFB, 0xFF, 0xBF]);
+        assert_eq!(URL_SAFE_NO_PAD.decode_lenient("+/+/").unwrap(), vec![0xFB, 0xFF, 0xBF]);
+        assert_eq!(STANDARD.decode_lenient("Zh==").unwrap(), b"f");
+        assert_eq!(STANDARD.decode_lenient("Zg=").err().unwrap().error_type, DecodeErrorType::InvalidPadding);
+        assert_eq!(STANDARD.decode_lenient("Zg=g").err().unwrap().error_type, DecodeErrorType::InvalidPadding);
+        assert_eq!(STANDARD.decode_lenient("Zm9vY").err().unwrap().error_type, DecodeErrorType::InvalidLength);
+        assert_eq!(STANDARD.decode_lenient("Zm9*").err().unwrap().error_type, DecodeErrorType::InvalidCharacter);
+    }
+
+    #[test]
+    fn test_decode_error_into_client_error() {
+        let client_error: ClientError = STANDARD.decode("Z").err().unwrap().into();
+        assert_eq!(client_error.error_type, ClientErrorType::InvalidBase64);
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(input in proptest::collection::vec(any::<u8>(), 0..256)) {
+            for encoding in ENCODINGS {
+                let encoded = encoding.encode(&input);
+                prop_assert_eq!(encoding.decode(&encoded).unwrap(), input.clone());
+                prop_assert_eq!(encoding.decode_lenient(&encoded).unwrap(), input.clone());
+                prop_assert_eq!(encoded.len() % 4 == 0, encoding.padding || input.len() % 3 == 0);
+            }
+        }
+
+        #[test]
+        fn test_decode_never_panics(input in "[A-Za-z0-9+/=_ -]{0,16}") {
+            for encoding in ENCODINGS {
+                let _ = encoding.decode(&input);
+                let _ = encoding.decode_lenient(&input);
+            }
+        }
+    }
+}
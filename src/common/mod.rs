@@ -1,6 +1,8 @@
+#[allow(clippy::module_inception)]
 mod common;
 
 pub mod urlencode;
 pub mod base64;
+pub mod request_items;
 
-pub use common::{ ClientError, ClientErrorType, Arguments, Parameters };
\ No newline at end of file
+pub use common::{ ClientError, ClientErrorType, Arguments, AuthType, Parameters };
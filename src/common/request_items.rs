@@ -0,0 +1,290 @@
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{ BuildHasher, Hasher };
+use std::path::Path;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use serde_json::{ Map, Value };
+
+use crate::common::{ ClientError, ClientErrorType };
+use crate::common::urlencode::{ self, EncodeSet };
+use crate::http::{ parse_field_line, HeaderMap };
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+const MULTIPART_CONTENT_TYPE: &str = "multipart/form-data";
+const FILE_CONTENT_TYPE: &str = "application/octet-stream";
+
+/**
+ * Separators of request items. When separators start at the same position the longest
+ * separator is used, so key:=value is a json field and key==value is a query parameter.
+ */
+const SEPARATORS: [&str; 5] = [":=", "==", "=", ":", "@"];
+
+/**
+ * A request item given on the command line.
+ * Example: Accept:application/json, name=value, count:=1, q==rust, file@./data.bin
+ */
+#[derive(Debug, PartialEq)]
+pub enum RequestItem {
+    // Name:value
+    Header(String, String),
+    // key==value
+    Query(String, String),
+    // key=value
+    DataField(String, String),
+    // key:=raw-json
+    JsonField(String, Value),
+    // field@path
+    FileField(String, String),
+}
+
+impl RequestItem {
+    /**
+     * Parses a request item.
+     * The item is split on the first separator. A separator character in the key can be
+     * escaped with a backslash.
+     * Example: "a\=b=c" is the data field "a=b" with the value "c".
+     */
+    pub fn parse(item: &str) -> Result<RequestItem, ClientError> {
+        let (key, separator, value) = split_item(item)?;
+        match separator {
+            ":" => {
+                let (name, value) = parse_field_line(&format!("{}:{}", key, value))?;
+                Ok(RequestItem::Header(name, value))
+            },
+            "==" => Ok(RequestItem::Query(key, value.to_string())),
+            "=" => Ok(RequestItem::DataField(key, value.to_string())),
+            ":=" => match serde_json::from_str(value) {
+                Ok(json) => Ok(RequestItem::JsonField(key, json)),
+                Err(err) => Err(ClientError::new(ClientErrorType::InvalidRequestItem, format!("Invalid json in request item {:?}: {}", item, err)))
+            },
+            _ => Ok(RequestItem::FileField(key, value.to_string()))
+        }
+    }
+}
+
+/**
+ * Request items collected into headers, query parameters and a body.
+ */
+#[derive(Debug, Default)]
+pub struct RequestItems {
+    pub headers: HeaderMap,
+    pub query: Vec<(String, String)>,
+    data_fields: Vec<(String, String)>,
+    json_fields: Vec<(String, Value)>,
+    file_fields: Vec<(String, String)>,
+}
+
+impl RequestItems {
+    /**
+     * Parses the request items in the order given.
+     */
+    pub fn parse(items: &[String]) -> Result<RequestItems, ClientError> {
+        let mut request_items = RequestItems::default();
+        for item in items {
+            match RequestItem::parse(item)? {
+                RequestItem::Header(name, value) => request_items.headers.append(&name, &value),
+                RequestItem::Query(key, value) => request_items.query.push((key, value)),
+                RequestItem::DataField(key, value) => request_items.data_fields.push((key, value)),
+                RequestItem::JsonField(key, value) => request_items.json_fields.push((key, value)),
+                RequestItem::FileField(key, value) => request_items.file_fields.push((key, value)),
+            }
+        }
+        Ok(request_items)
+    }
+
+    /**
+     * Returns true if the items contain fields for the body.
+     */
+    pub fn has_body(&self) -> bool {
+        !self.data_fields.is_empty() || !self.json_fields.is_empty() || !self.file_fields.is_empty()
+    }
+
+    /**
+     * Get the body from the fields and its content type.
+     * Fields are sent as a json object, as a form if form is true, or as multipart/form-data
+     * if a file field is given. Json fields can only be sent in a json object.
+     *
+     * Returns None if there are no fields.
+     */
+    pub fn get_body(&self, form: bool) -> Result<Option<(String, Vec<u8>)>, ClientError> {
+        if !self.has_body() {
+            return Ok(None);
+        }
+        if (form || !self.file_fields.is_empty()) && !self.json_fields.is_empty() {
+            return Err(ClientError::new(ClientErrorType::InvalidRequestItem, "Json fields cannot be sent in a form".to_string()));
+        }
+        if !self.file_fields.is_empty() {
+            let boundary = get_boundary();
+            let body = self.get_multipart_body(&boundary)?;
+            Ok(Some((format!("{}; boundary={}", MULTIPART_CONTENT_TYPE, boundary), body)))
+        } else if form {
+            Ok(Some((FORM_CONTENT_TYPE.to_string(), self.get_form_body())))
+        } else {
+            Ok(Some((JSON_CONTENT_TYPE.to_string(), self.get_json_body())))
+        }
+    }
+
+    /**
+     * Get the fields as a json object. Data fields are json strings.
+     */
+    fn get_json_body(&self) -> Vec<u8> {
+        let mut object: Map<String, Value> = Map::new();
+        for (key, value) in &self.data_fields {
+            object.insert(key.clone(), Value::String(value.clone()));
+        }
+        for (key, value) in &self.json_fields {
+            object.insert(key.clone(), value.clone());
+        }
+        Value::Object(object).to_string().into_bytes()
+    }
+
+    /**
+     * Get the data fields as an url encoded form.
+     * Example: name=John+Doe&age=30
+     */
+    fn get_form_body(&self) -> Vec<u8> {
+        self.data_fields.iter()
+            .map(|(key, value)| format!("{}={}", urlencode::encode_with(key, EncodeSet::Form), urlencode::encode_with(value, EncodeSet::Form)))
+            .collect::<Vec<String>>()
+            .join("&")
+            .into_bytes()
+    }
+
+    /**
+     * Get the data fields and files as a multipart/form-data body as described in RFC 7578.
+     */
+    fn get_multipart_body(&self, boundary: &str) -> Result<Vec<u8>, ClientError> {
+        let mut body: Vec<u8> = Vec::new();
+        for (key, value) in &self.data_fields {
+            body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n", boundary, escape_quoted(key)).as_bytes());
+            body.extend(value.as_bytes());
+            body.extend(b"\r\n");
+        }
+        for (key, path) in &self.file_fields {
+            let content = match fs::read(path) {
+                Ok(content) => content,
+                Err(err) => return Err(ClientError::new(ClientErrorType::ReadError, format!("Could not read file {}: {}", path, err)))
+            };
+            let file_name = Path::new(path).file_name().map(|file_name| file_name.to_string_lossy().to_string()).unwrap_or_default();
+            body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n", boundary, escape_quoted(key), escape_quoted(&file_name), FILE_CONTENT_TYPE).as_bytes());
+            body.extend(content);
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("--{}--\r\n", boundary).as_bytes());
+        Ok(body)
+    }
+}
+
+/**
+ * Splits a request item into key, separator and value.
+ * Returns an error if the item has no separator or the key is empty.
+ */
+fn split_item(item: &str) -> Result<(String, &'static str, &str), ClientError> {
+    let mut key = String::new();
+    let mut escaped = false;
+    for (index, character) in item.char_indices() {
+        if escaped {
+            key.push(character);
+            escaped = false;
+            continue;
+        }
+        if character == '\\' && item[index + 1..].starts_with([':', '=', '@', '\\']) {
+            escaped = true;
+            continue;
+        }
+        if let Some(separator) = SEPARATORS.iter().find(|separator| item[index..].starts_with(**separator)) {
+            if key.is_empty() {
+                break;
+            }
+            return Ok((key, separator, &item[index + separator.len()..]));
+        }
+        key.push(character);
+    }
+    Err(ClientError::new(ClientErrorType::InvalidRequestItem, format!("Invalid request item {:?}", item)))
+}
+
+/**
+ * Escapes quotes and backslashes in a quoted string.
+ */
+fn escape_quoted(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/**
+ * Get a boundary for a multipart body that is unlikely to occur in the content.
+ */
+fn get_boundary() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos()).unwrap_or_default());
+    format!("http-client-boundary-{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_item() {
+        assert_eq!(RequestItem::parse("Accept:text/html, application/json").unwrap(), RequestItem::Header("Accept".to_string(), "text/html, application/json".to_string()));
+        assert_eq!(RequestItem::parse("Referer: http://localhost:8080/").unwrap(), RequestItem::Header("Referer".to_string(), "http://localhost:8080/".to_string()));
+        assert_eq!(RequestItem::parse("q==a=b").unwrap(), RequestItem::Query("q".to_string(), "a=b".to_string()));
+        assert_eq!(RequestItem::parse("name=John:Doe").unwrap(), RequestItem::DataField("name".to_string(), "John:Doe".to_string()));
+        assert_eq!(RequestItem::parse("count:=[1, 2]").unwrap(), RequestItem::JsonField("count".to_string(), serde_json::json!([1, 2])));
+        assert_eq!(RequestItem::parse("file@./data.bin").unwrap(), RequestItem::FileField("file".to_string(), "./data.bin".to_string()));
+        assert_eq!(RequestItem::parse("a\\=b=c").unwrap(), RequestItem::DataField("a=b".to_string(), "c".to_string()));
+    }
+
+    #[test]
+    fn test_parse_item_invalid() {
+        for item in ["novalue", "=value", ":value", "count:=[1", "Bad Name:value"] {
+            assert!(RequestItem::parse(item).is_err(), "{:?}", item);
+        }
+        assert_eq!(RequestItem::parse("novalue").err().unwrap().error_type, ClientErrorType::InvalidRequestItem);
+    }
+
+    #[test]
+    fn test_get_body_json() {
+        let request_items = RequestItems::parse(&["name=John".to_string(), "age:=30".to_string(), "X-Id:1".to_string(), "q==rust".to_string()]).unwrap();
+        let (content_type, body) = request_items.get_body(false).unwrap().unwrap();
+        assert_eq!(content_type, "application/json");
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json, serde_json::json!({"name": "John", "age": 30}));
+        assert_eq!(request_items.headers.get("x-id"), Some("1"));
+        assert_eq!(request_items.query, vec![("q".to_string(), "rust".to_string())]);
+    }
+
+    #[test]
+    fn test_get_body_form() {
+        let request_items = RequestItems::parse(&["name=John Doe".to_string(), "age=30".to_string()]).unwrap();
+        let (content_type, body) = request_items.get_body(true).unwrap().unwrap();
+        assert_eq!(content_type, "application/x-www-form-urlencoded");
+        assert_eq!(body, b"name=John+Doe&age=30");
+        let request_items = RequestItems::parse(&["age:=30".to_string()]).unwrap();
+        assert_eq!(request_items.get_body(true).err().unwrap().error_type, ClientErrorType::InvalidRequestItem);
+    }
+
+    #[test]
+    fn test_get_body_multipart() {
+        let directory = std::env::temp_dir().join(format!("http-client-test-{}-get-body-multipart", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("upload.txt");
+        fs::write(&path, b"content").unwrap();
+        let request_items = RequestItems::parse(&["name=John".to_string(), format!("upload@{}", path.display())]).unwrap();
+        let (content_type, body) = request_items.get_body(false).unwrap().unwrap();
+        let boundary = content_type.strip_prefix("multipart/form-data; boundary=").unwrap();
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.starts_with(&format!("--{}\r\nContent-Disposition: form-data; name=\"name\"\r\n\r\nJohn\r\n", boundary)));
+        assert!(body.contains("name=\"upload\"; filename=\"upload.txt\"\r\nContent-Type: application/octet-stream\r\n\r\ncontent\r\n"));
+        assert!(body.ends_with(&format!("--{}--\r\n", boundary)));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_get_body_none() {
+        let request_items = RequestItems::parse(&["Accept:text/html".to_string()]).unwrap();
+        assert!(!request_items.has_body());
+        assert!(request_items.get_body(false).unwrap().is_none());
+    }
+}
@@ -1,25 +1,58 @@
+use std::fmt;
+
+use crate::common::{ ClientError, ClientErrorType };
+
+/**
+ * Type of URL decoding error.
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DecodeErrorType {
+    // A % is not followed by two characters.
+    IncompleteEscape,
+    // A % is followed by a character that is not a hexadecimal digit.
+    InvalidHexDigit,
+    // The decoded bytes are not valid UTF-8.
+    InvalidUtf8,
+}
+
 /**
  * URL decoding error. This error is thrown when
  * the URL encoded string can't be decoded to a normal
- * string. The error contains a message with the reason.
+ * string. The error contains the type and a message with the reason.
  */
-#[derive(Debug)]
-struct DecodeError {
-    message: String
+#[derive(Debug, PartialEq)]
+pub struct DecodeError {
+    pub error_type: DecodeErrorType,
+    pub message: String
 }
 
 impl DecodeError {
-    pub fn new(message: String) -> DecodeError {
+    pub fn new(error_type: DecodeErrorType, message: String) -> DecodeError {
         DecodeError {
+            error_type,
             message
         }
     }
 }
 
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<DecodeError> for ClientError {
+    fn from(err: DecodeError) -> ClientError {
+        ClientError::new(ClientErrorType::InvalidUrlEncoding, err.message)
+    }
+}
+
 /**
- * Characters that don't need to be encoded. Im the URI 
+ * Characters that don't need to be encoded. Im the URI
  * standard, these are called "unreserved characters".
- * 
+ *
  * @See https://tools.ietf.org/html/rfc3986#section-2.3
  */
 pub const UNRESERVED_CHARACTERS: [char; 66] = [
@@ -30,122 +63,204 @@ pub const UNRESERVED_CHARACTERS: [char; 66] = [
     '4', '5', '6', '7', '8', '9', '-', '.', '_', '~',
 ];
 
+/**
+ * Characters used to delimit parts of a component. In the URI
+ * standard, these are called "sub-delims".
+ *
+ * @See https://tools.ietf.org/html/rfc3986#section-2.2
+ */
+pub const SUB_DELIMS: [char; 11] = ['!', '$', '&', '\'', '(', ')', '*', '+', ',', ';', '='];
+
+/**
+ * Sets of characters that are left unencoded.
+ * All other characters are percent encoded as UTF-8 bytes.
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EncodeSet {
+    // Unreserved characters only. Safe in any component.
+    Component,
+    // Characters allowed in a path segment, pchar without /.
+    // @See https://tools.ietf.org/html/rfc3986#section-3.3
+    PathSegment,
+    // Characters allowed in a query key or value. & = + and # are encoded
+    // so they are not confused with the query delimiters.
+    // @See https://tools.ietf.org/html/rfc3986#section-3.4
+    Query,
+    // Characters allowed in the user or password of userinfo. : is encoded as it
+    // separates the user from the password.
+    // @See https://tools.ietf.org/html/rfc3986#section-3.2.1
+    Userinfo,
+    // application/x-www-form-urlencoded. Space is encoded as +.
+    // @See https://url.spec.whatwg.org/#application/x-www-form-urlencoded
+    Form,
+}
+
+impl EncodeSet {
+    /**
+     * Returns true if the character is left unencoded in the set.
+     */
+    fn is_allowed(&self, character: char) -> bool {
+        if UNRESERVED_CHARACTERS.contains(&character) {
+            return *self != EncodeSet::Form || character != '~';
+        }
+        match self {
+            EncodeSet::Component => false,
+            EncodeSet::PathSegment => SUB_DELIMS.contains(&character) || character == ':' || character == '@',
+            EncodeSet::Query => (SUB_DELIMS.contains(&character) && !matches!(character, '&' | '=' | '+')) || matches!(character, ':' | '@' | '/' | '?'),
+            EncodeSet::Userinfo => SUB_DELIMS.contains(&character),
+            EncodeSet::Form => character == '*',
+        }
+    }
+}
+
 /**
  * Encodes a string to a URL encoded string.
- * 
+ * Only unreserved characters are left unencoded.
+ *
  * @param input The string to encode.
- * 
+ *
  * @return The URL encoded string.
  */
 pub fn encode(input: &str) -> String {
+    encode_with(input, EncodeSet::Component)
+}
+
+/**
+ * Encodes a string to a URL encoded string using the encode set.
+ *
+ * @param input The string to encode.
+ * @param encode_set The characters to leave unencoded.
+ *
+ * @return The URL encoded string.
+ */
+pub fn encode_with(input: &str, encode_set: EncodeSet) -> String {
     let mut result = String::new();
     for character in input.chars() {
-        result.push_str(encode_char(&character).as_str());
+        if encode_set == EncodeSet::Form && character == ' ' {
+            result.push('+');
+        } else if encode_set.is_allowed(character) {
+            result.push(character);
+        } else {
+            let mut buffer = [0; 4];
+            encode_bytes(character.encode_utf8(&mut buffer).as_bytes(), &mut result);
+        }
     }
     result
 }
 
 /**
- * Encodes a character to a URL encoded string.
- * 
- * @param character The character to encode.
- * 
- * @return The URL encoded character.
+ * Encodes each segment of a path using the encode set.
+ * The / separating the segments are left unencoded.
+ *
+ * @param input The path to encode.
+ * @param encode_set The characters to leave unencoded in a segment.
+ *
+ * @return The URL encoded path.
  */
-fn encode_char(character: &char) -> String {
-    if UNRESERVED_CHARACTERS.contains(&character) {
-        character.to_string()
-    } else {
-        let string = character.to_string();
-        let bytes = string.as_bytes();
-        encode_non_reserved_char(bytes)
-    }
+pub fn encode_path(input: &str, encode_set: EncodeSet) -> String {
+    input.split('/')
+        .map(|segment| encode_with(segment, encode_set))
+        .collect::<Vec<String>>()
+        .join("/")
 }
 
 /**
- * Encodes a byte array of a non reserved
- * character to a URL encoded string.
- * 
- * @param bytes The byte array to encode.
- * 
- * @return The URL encoded string.
+ * Encodes bytes as %XX with uppercase hexadecimal digits.
+ *
+ * @param bytes The bytes to encode.
+ * @param result The string to append the encoded bytes to.
  */
-fn encode_non_reserved_char(bytes: &[u8]) -> String {
-    let mut result = String::new();
-    for byte in bytes {            
+fn encode_bytes(bytes: &[u8], result: &mut String) {
+    for byte in bytes {
         result.push('%');
-        result.push_str(&format!("{:X}", byte));
+        result.push_str(&format!("{:02X}", byte));
     }
-    result
 }
 
 /**
  * Decodes a URL encoded string to a normal string.
- * 
+ * Percent encoded bytes are collected and must form valid UTF-8.
+ *
  * @param input The URL encoded string.
- * 
+ *
  * @return The normal string.
  */
-pub fn decode(str: &str) -> Result<String, DecodeError> {
-    let mut result = String::new();
-    let mut chars = str.chars();
-    while let Some(character) = chars.next() {
-        if character == '%' {
-            let bytes = get_bytes(&mut chars)?;
-            let utf8 = match String::from_utf8(bytes) {
-                Ok(utf8) => utf8,
-                Err(err) => return Err(DecodeError::new(err.to_string()))
-            };
-            result.push_str(utf8.as_str());
-        } else {
-            result.push(character);
-        }
-    }
-    Ok(result)
+pub fn decode(input: &str) -> Result<String, DecodeError> {
+    decode_bytes(input, false)
+}
+
+/**
+ * Decodes an application/x-www-form-urlencoded string to a normal string.
+ * + is decoded as space.
+ *
+ * @param input The form encoded string.
+ *
+ * @return The normal string.
+ */
+pub fn decode_form(input: &str) -> Result<String, DecodeError> {
+    decode_bytes(input, true)
 }
 
 /**
- * Gets the bytes of a URL encoded character.
- * 
- * @param chars The iterator of characters.
- * 
- * @return The bytes of the URL encoded character.
+ * Decodes percent encoded bytes, and + as space if form is true.
+ *
+ * @param input The URL encoded string.
+ * @param form Decode + as space.
+ *
+ * @return The normal string.
  */
-fn get_bytes(chars: &mut std::str::Chars) -> Result<Vec<u8>, DecodeError> {
-    let mut bytes: Vec<u8> = Vec::new();
-    let byte = get_byte(chars)?;
-    bytes.push(byte);
-    if byte >= 128 {
-        chars.next();
-        bytes.push(get_byte(chars)?);
+fn decode_bytes(input: &str, form: bool) -> Result<String, DecodeError> {
+    let bytes = input.as_bytes();
+    let mut result: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                if bytes.len() - index < 3 {
+                    return Err(DecodeError::new(DecodeErrorType::IncompleteEscape, format!("Incomplete percent encoding at position {}", index)));
+                }
+                result.push(get_hex_value(bytes[index + 1], index + 1)? << 4 | get_hex_value(bytes[index + 2], index + 2)?);
+                index += 3;
+            },
+            b'+' if form => {
+                result.push(b' ');
+                index += 1;
+            },
+            byte => {
+                result.push(byte);
+                index += 1;
+            }
+        }
+    }
+    match String::from_utf8(result) {
+        Ok(result) => Ok(result),
+        Err(err) => Err(DecodeError::new(DecodeErrorType::InvalidUtf8, format!("Decoded bytes are not valid UTF-8: {}", err)))
     }
-    Ok(bytes)
 }
 
 /**
- * Gets a byte from the URL encoded string.
- * 
- * @param chars The iterator of characters.
- * 
- * @return The byte.
+ * Gets the value of a hexadecimal digit.
+ *
+ * @param digit The hexadecimal digit.
+ * @param position The position of the digit, used in the error message.
+ *
+ * @return The value of the digit.
  */
-fn get_byte(chars: &mut std::str::Chars) -> Result<u8,DecodeError> {
-    let char1 = match chars.next() {
-        Some(char) => char,
-        None => return Err(DecodeError::new("Unexpected end of string".to_string()))
-    };
-    let char2 = match chars.next() {
-        Some(char) => char,
-        None => return Err(DecodeError::new("Unexpected end of string".to_string()))
-    };
-    match u8::from_str_radix(format!("{}{}", char1, char2).as_str(), 16) {
-        Ok(byte) => Ok(byte),
-        Err(err) => Err(DecodeError::new(err.to_string()))
+fn get_hex_value(digit: u8, position: usize) -> Result<u8, DecodeError> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => Err(DecodeError::new(DecodeErrorType::InvalidHexDigit, format!("Invalid hexadecimal digit at position {}", position)))
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    const ENCODE_SETS: [EncodeSet; 5] = [EncodeSet::Component, EncodeSet::PathSegment, EncodeSet::Query, EncodeSet::Userinfo, EncodeSet::Form];
 
     #[test]
     fn test_encode() {
@@ -153,6 +268,24 @@ mod test {
         assert_eq!(super::encode("http://localhost:8080"), "http%3A%2F%2Flocalhost%3A8080");
         assert_eq!(super::encode("Hello Günter"), "Hello%20G%C3%BCnter");
         assert_eq!(super::encode("æøåÆØÅ,.-;:_!\"#¤%"), "%C3%A6%C3%B8%C3%A5%C3%86%C3%98%C3%85%2C.-%3B%3A_%21%22%23%C2%A4%25");
+        assert_eq!(super::encode("\n\t€𝄞"), "%0A%09%E2%82%AC%F0%9D%84%9E");
+    }
+
+    #[test]
+    fn test_encode_with() {
+        let input = "a b/c?d&e=f+g:h@i#j!k~";
+        assert_eq!(encode_with(input, EncodeSet::PathSegment), "a%20b%2Fc%3Fd&e=f+g:h@i%23j!k~");
+        assert_eq!(encode_with(input, EncodeSet::Query), "a%20b/c?d%26e%3Df%2Bg:h@i%23j!k~");
+        assert_eq!(encode_with(input, EncodeSet::Userinfo), "a%20b%2Fc%3Fd&e=f+g%3Ah%40i%23j!k~");
+        assert_eq!(encode_with(input, EncodeSet::Form), "a+b%2Fc%3Fd%26e%3Df%2Bg%3Ah%40i%23j%21k%7E");
+        assert_eq!(encode_with("*-._", EncodeSet::Form), "*-._");
+    }
+
+    #[test]
+    fn test_encode_path() {
+        assert_eq!(encode_path("/docs/a b/ü.txt", EncodeSet::Component), "/docs/a%20b/%C3%BC.txt");
+        assert_eq!(encode_path("/a%2Fb/", EncodeSet::Component), "/a%252Fb/");
+        assert_eq!(encode_path("", EncodeSet::Component), "");
     }
 
     #[test]
@@ -161,5 +294,52 @@ mod test {
         assert_eq!(super::decode("http%3A%2F%2Flocalhost%3A8080").unwrap(), "http://localhost:8080");
         assert_eq!(super::decode("Hello%20G%C3%BCnter").unwrap(), "Hello Günter");
         assert_eq!(super::decode("%C3%A6%C3%B8%C3%A5%C3%86%C3%98%C3%85%2C.-%3B%3A_%21%22%23%C2%A4%25").unwrap(), "æøåÆØÅ,.-;:_!\"#¤%");
+        assert_eq!(super::decode("%e2%82%ac%F0%9D%84%9E").unwrap(), "€𝄞");
+        assert_eq!(super::decode("a+b").unwrap(), "a+b");
+        assert_eq!(decode_form("a+b%2B").unwrap(), "a b+");
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert_eq!(decode("%").err().unwrap().error_type, DecodeErrorType::IncompleteEscape);
+        assert_eq!(decode("abc%4").err().unwrap().error_type, DecodeErrorType::IncompleteEscape);
+        assert_eq!(decode("%4G").err().unwrap().error_type, DecodeErrorType::InvalidHexDigit);
+        assert_eq!(decode("%+1").err().unwrap().error_type, DecodeErrorType::InvalidHexDigit);
+        assert_eq!(decode("%C3").err().unwrap().error_type, DecodeErrorType::InvalidUtf8);
+        assert_eq!(decode("%E2%82").err().unwrap().error_type, DecodeErrorType::InvalidUtf8);
+        assert_eq!(decode("%FF").err().unwrap().error_type, DecodeErrorType::InvalidUtf8);
+        assert_eq!(decode("%ED%A0%80").err().unwrap().error_type, DecodeErrorType::InvalidUtf8);
+    }
+
+    #[test]
+    fn test_decode_error_into_client_error() {
+        let client_error: ClientError = decode("%").err().unwrap().into();
+        assert_eq!(client_error.error_type, ClientErrorType::InvalidUrlEncoding);
     }
-}
\ No newline at end of file
+
+    proptest! {
+        #[test]
+        fn test_round_trip(input in any::<String>()) {
+            for encode_set in ENCODE_SETS {
+                let encoded = encode_with(&input, encode_set);
+                let decoded = if encode_set == EncodeSet::Form { decode_form(&encoded) } else { decode(&encoded) };
+                prop_assert_eq!(decoded.unwrap(), input.clone());
+            }
+        }
+
+        #[test]
+        fn test_encoded_is_ascii(input in any::<String>()) {
+            for encode_set in ENCODE_SETS {
+                let encoded = encode_with(&input, encode_set);
+                prop_assert!(encoded.bytes().all(|byte| byte.is_ascii_graphic()));
+                prop_assert!(!encoded.contains('#'));
+            }
+        }
+
+        #[test]
+        fn test_decode_never_panics(input in "[%0-9A-Za-z+]{0,16}") {
+            let _ = decode(&input);
+            let _ = decode_form(&input);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::io::Read;
+
+use crate::auth::fill_random;
+use crate::common::{ ClientError, ClientErrorType };
+
+pub(crate) const SSL_3_0: u16 = 0x0300;
+pub(crate) const TLS_1_0: u16 = 0x0301;
+pub(crate) const TLS_1_1: u16 = 0x0302;
+pub(crate) const TLS_1_2: u16 = 0x0303;
+
+const CONTENT_TYPE_ALERT: u8 = 0x15;
+const CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+const HANDSHAKE_SERVER_HELLO: u8 = 0x02;
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const EXTENSION_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXTENSION_EC_POINT_FORMATS: u16 = 0x000b;
+const EXTENSION_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXTENSION_RENEGOTIATION_INFO: u16 = 0xff01;
+// x25519, secp256r1, secp384r1, secp521r1, ffdhe2048
+const SUPPORTED_GROUPS: &[u16] = &[0x001d, 0x0017, 0x0018, 0x0019, 0x0100];
+// ecdsa and rsa-pss with sha256, sha384, sha512, rsa pkcs1 with sha256, sha384, sha512, sha1, ecdsa with sha1
+const SIGNATURE_ALGORITHMS: &[u16] = &[0x0403, 0x0503, 0x0603, 0x0804, 0x0805, 0x0806, 0x0401, 0x0501, 0x0601, 0x0201, 0x0203];
+const MAX_RECORD_LENGTH: usize = 16384 + 2048;
+
+/**
+ * Version and cipher suite chosen by the server in its ServerHello.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ServerHello {
+    pub version: u16,
+    pub cipher_suite: u16
+}
+
+/**
+ * Get the name of a protocol version. Example: TLSv1.0
+ */
+pub(crate) fn get_version_name(version: u16) -> String {
+    match version {
+        SSL_3_0 => "SSLv3".to_string(),
+        TLS_1_0 => "TLSv1.0".to_string(),
+        TLS_1_1 => "TLSv1.1".to_string(),
+        TLS_1_2 => "TLSv1.2".to_string(),
+        version => format!("0x{:04x}", version)
+    }
+}
+
+/**
+ * Builds a TLS record with a ClientHello, as described in RFC 5246 section 7.4.1.2.
+ * The hello offers the version and the cipher suites, with the extensions servers commonly
+ * require. It is only used to read the ServerHello, to probe for protocol versions and cipher
+ * suites rustls does not implement. The server name is sent with SNI if there is one.
+ */
+pub(crate) fn build_client_hello(version: u16, cipher_suites: &[u16], server_name: Option<&str>) -> Result<Vec<u8>, ClientError> {
+    let mut random = [0u8; 32];
+    fill_random(&mut random)?;
+    let mut extensions: Vec<u8> = Vec::new();
+    if let Some(server_name) = server_name {
+        let mut server_name_list: Vec<u8> = vec![0];
+        push_vector(&mut server_name_list, 2, server_name.as_bytes());
+        let mut extension: Vec<u8> = Vec::new();
+        push_vector(&mut extension, 2, &server_name_list);
+        push_extension(&mut extensions, EXTENSION_SERVER_NAME, &extension);
+    }
+    let mut extension: Vec<u8> = Vec::new();
+    push_vector(&mut extension, 2, &to_bytes(SUPPORTED_GROUPS));
+    push_extension(&mut extensions, EXTENSION_SUPPORTED_GROUPS, &extension);
+    push_extension(&mut extensions, EXTENSION_EC_POINT_FORMATS, &[1, 0]);
+    if version >= TLS_1_2 {
+        let mut extension: Vec<u8> = Vec::new();
+        push_vector(&mut extension, 2, &to_bytes(SIGNATURE_ALGORITHMS));
+        push_extension(&mut extensions, EXTENSION_SIGNATURE_ALGORITHMS, &extension);
+    }
+    push_extension(&mut extensions, EXTENSION_RENEGOTIATION_INFO, &[0]);
+    let mut client_hello: Vec<u8> = Vec::new();
+    client_hello.extend_from_slice(&version.to_be_bytes());
+    client_hello.extend_from_slice(&random);
+    client_hello.push(0);
+    push_vector(&mut client_hello, 2, &to_bytes(cipher_suites));
+    client_hello.extend_from_slice(&[1, 0]);
+    push_vector(&mut client_hello, 2, &extensions);
+    let mut handshake: Vec<u8> = vec![HANDSHAKE_CLIENT_HELLO];
+    push_vector(&mut handshake, 3, &client_hello);
+    let mut record: Vec<u8> = vec![CONTENT_TYPE_HANDSHAKE];
+    record.extend_from_slice(&version.min(TLS_1_0).to_be_bytes());
+    push_vector(&mut record, 2, &handshake);
+    Ok(record)
+}
+
+/**
+ * Reads the ServerHello answering a ClientHello.
+ * Returns None if the server refuses the hello with an alert or closes the connection.
+ * Returns an error if the server answers with something else.
+ */
+pub(crate) fn read_server_hello(reader: &mut impl Read) -> Result<Option<ServerHello>, ClientError> {
+    let mut header = [0u8; 5];
+    if reader.read_exact(&mut header).is_err() {
+        return Ok(None);
+    }
+    let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+    match header[0] {
+        CONTENT_TYPE_ALERT => return Ok(None),
+        CONTENT_TYPE_HANDSHAKE if length <= MAX_RECORD_LENGTH => {},
+        _ => return Err(ClientError::new(ClientErrorType::TlsError, "Server did not answer the ClientHello with a handshake record".to_string()))
+    }
+    let mut record = vec![0u8; length];
+    if reader.read_exact(&mut record).is_err() {
+        return Ok(None);
+    }
+    // Handshake type and length, version, random, session id
+    if record.len() < 39 || record[0] != HANDSHAKE_SERVER_HELLO {
+        return Err(ClientError::new(ClientErrorType::TlsError, "Server did not answer the ClientHello with a ServerHello".to_string()));
+    }
+    let session_id_length = record[38] as usize;
+    match record.get(39 + session_id_length..41 + session_id_length) {
+        Some(cipher_suite) => Ok(Some(ServerHello {
+            version: u16::from_be_bytes([record[4], record[5]]),
+            cipher_suite: u16::from_be_bytes([cipher_suite[0], cipher_suite[1]])
+        })),
+        None => Err(ClientError::new(ClientErrorType::TlsError, "ServerHello is truncated".to_string()))
+    }
+}
+
+fn to_bytes(values: &[u16]) -> Vec<u8> {
+    values.iter().flat_map(|value| value.to_be_bytes()).collect()
+}
+
+/**
+ * Appends the data prefixed with its length in length_size bytes.
+ */
+fn push_vector(buffer: &mut Vec<u8>, length_size: usize, data: &[u8]) {
+    buffer.extend_from_slice(&data.len().to_be_bytes()[std::mem::size_of::<usize>() - length_size..]);
+    buffer.extend_from_slice(data);
+}
+
+fn push_extension(buffer: &mut Vec<u8>, extension_type: u16, data: &[u8]) {
+    buffer.extend_from_slice(&extension_type.to_be_bytes());
+    push_vector(buffer, 2, data);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_client_hello() {
+        let record = build_client_hello(TLS_1_2, &[0xc02b, 0x002f], Some("localhost")).unwrap();
+        assert_eq!(&record[..3], &[CONTENT_TYPE_HANDSHAKE, 0x03, 0x01]);
+        assert_eq!(u16::from_be_bytes([record[3], record[4]]) as usize, record.len() - 5);
+        assert_eq!(record[5], HANDSHAKE_CLIENT_HELLO);
+        assert_eq!(&record[9..11], &[0x03, 0x03]);
+        // Empty session id, then the cipher suites
+        assert_eq!(&record[43..50], &[0, 0, 4, 0xc0, 0x2b, 0x00, 0x2f]);
+        assert!(record.windows(9).any(|window| window == b"localhost"));
+        let record = build_client_hello(SSL_3_0, &[0x000a], None).unwrap();
+        assert_eq!(&record[1..3], &[0x03, 0x00]);
+        // Without the signature algorithms extension
+        let tls_1_2_record = build_client_hello(TLS_1_2, &[0x000a], None).unwrap();
+        assert_eq!(tls_1_2_record.len() - record.len(), 6 + 2 * SIGNATURE_ALGORITHMS.len());
+    }
+
+    #[test]
+    fn test_read_server_hello() {
+        let mut server_hello = vec![CONTENT_TYPE_HANDSHAKE, 0x03, 0x03, 0x00, 0x2a, HANDSHAKE_SERVER_HELLO, 0x00, 0x00, 0x26, 0x03, 0x02];
+        server_hello.extend_from_slice(&[0u8; 32]);
+        server_hello.extend_from_slice(&[0, 0xc0, 0x13, 0]);
+        let expected = ServerHello { version: TLS_1_1, cipher_suite: 0xc013 };
+        assert_eq!(read_server_hello(&mut server_hello.as_slice()).unwrap(), Some(expected));
+        assert_eq!(read_server_hello(&mut [CONTENT_TYPE_ALERT, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28].as_slice()).unwrap(), None);
+        assert_eq!(read_server_hello(&mut [].as_slice()).unwrap(), None);
+        assert_eq!(read_server_hello(&mut b"HTTP/1.1 400 Bad Request\r\n\r\n".as_slice()).err().unwrap().error_type, ClientErrorType::TlsError);
+        assert_eq!(get_version_name(TLS_1_1), "TLSv1.1");
+    }
+}
@@ -1,10 +1,53 @@
-use std::io::{Read, Write};
-use std::net::{ ToSocketAddrs, TcpStream };
+use std::io::{ErrorKind, Read, Write};
+use std::net::{ Shutdown, ToSocketAddrs, TcpStream };
 use std::time::Duration;
 use std::option::Option;
 
 use crate::common::{ ClientError, ClientErrorType};
+use crate::connection::TlsInfo;
 
+/**
+ * A connection the http client can send requests over.
+ * Implemented by the plain tcp connection and the tls connection layered on top of it.
+ * Responses are read from the connection using the Read implementation.
+ */
+pub trait Connection: Read + Send {
+    /**
+     * Connects to the host and port.
+     * Returns an error if the connection fails.
+     */
+    fn connect(&mut self) -> Result<(), ClientError>;
+
+    /**
+     * Writes data to the connection.
+     * Returns an error if the write fails.
+     */
+    fn write(&mut self, data: &[u8]) -> Result<(), ClientError>;
+
+    /**
+     * Returns true if the connection is not connected.
+     */
+    fn is_not_connected(&self) -> bool;
+
+    /**
+     * Returns true if an idle connection can no longer be used, because the server
+     * has closed it or has sent data without a request.
+     */
+    fn is_stale(&mut self) -> bool;
+
+    /**
+     * Closes the connection. The connection can be connected again.
+     */
+    fn close(&mut self);
+
+    /**
+     * Get the result of the tls handshake. Returns None if the connection is not a connected
+     * tls connection.
+     */
+    fn get_tls_info(&self) -> Option<TlsInfo> {
+        None
+    }
+}
 
 /**
  * Handles tcp connection to a host and port, with a connection timeout.
@@ -18,6 +61,11 @@ pub struct TcpConnection {
 }
 
 impl TcpConnection {
+    /**
+     * Read timeout used while waiting for data from the server.
+     */
+    pub const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
     /**
      * Creates a new TcpConnection with a host, port and connection timeout.
      */
@@ -46,23 +94,27 @@ impl TcpConnection {
             None => { return Err(ClientError::new(ClientErrorType::IncorrectSocketAddr,"Could not get socket address".to_string())) }
         };
         let stream_result: Result<TcpStream, std::io::Error> = TcpStream::connect_timeout(&socket_addr, self.connection_timeout);
-        self.tcp_stream = match stream_result {
-            Ok(stream) => Some(stream),
+        let stream = match stream_result {
+            Ok(stream) => stream,
             Err(err) => return Err(ClientError::new(ClientErrorType::ConnectionFailure, err.to_string()))
         };
+        if let Err(err) = stream.set_read_timeout(Some(Self::READ_TIMEOUT)) {
+            return Err(ClientError::new(ClientErrorType::ConnectionFailure, err.to_string()));
+        }
+        self.tcp_stream = Some(stream);
         Ok(())
     }
 
     /**
-     * Writes a request string to the tcp stream.
+     * Writes data to the tcp stream.
      * Returns an error if the write fails.
      */
-    pub fn write(&mut self, request_str: &String) -> Result<(), ClientError>{
+    pub fn write(&mut self, data: &[u8]) -> Result<(), ClientError>{
         let mut tcp_stream = match &self.tcp_stream {
             None => return Err(ClientError::new(ClientErrorType::NoAvailableTcpStream, "Could not retrieve tcp stream".to_string())),
             Some(tcp_stream) => tcp_stream
         };
-        let write_result = tcp_stream.write_all(request_str.as_bytes());
+        let write_result = tcp_stream.write_all(data);
         match write_result {
             Ok(_) => Ok(()),
             Err(_) => Err(ClientError::new(ClientErrorType::WriteError, "Could not write data".to_string()))
@@ -70,38 +122,66 @@ impl TcpConnection {
     }
 
     /**
-     * Reads data from the tcp stream.
-     * Returns an error if the read fails.
+     * Returns true if the tcp stream is not connected.
      */
-    pub fn read(self) -> Result<String, ClientError> {
-        let mut tcp_stream = match self.tcp_stream {
-            None => return Err(ClientError::new(ClientErrorType::NoAvailableTcpStream, "Could not retrieve tcp stream".to_string())),
+    pub fn is_not_connected(&self) -> bool  {
+        self.tcp_stream.is_none()
+    }
+
+    /**
+     * Returns true if the tcp stream is not connected, has been closed by the server
+     * or has data available to read.
+     * Checks the stream without blocking.
+     */
+    pub fn is_stale(&self) -> bool {
+        let tcp_stream = match &self.tcp_stream {
+            None => return true,
             Some(tcp_stream) => tcp_stream
         };
-        let _ = tcp_stream.set_read_timeout(Some(Duration::from_secs(5)));
-        let mut buffer:Vec<u8> = Vec::new();
-        let read_result = tcp_stream.read_to_end(buffer.as_mut());
-        match read_result {
-            Ok(_) => {
-                Ok(String::from_utf8(buffer).unwrap())
-            },
-            Err(_) => Err(ClientError::new(ClientErrorType::WriteError, "Could not read data".to_string()))
+        if tcp_stream.set_nonblocking(true).is_err() {
+            return true;
         }
+        let mut buffer = [0u8; 1];
+        let peek_result = tcp_stream.peek(&mut buffer);
+        let blocking_result = tcp_stream.set_nonblocking(false);
+        let would_block = matches!(peek_result, Err(err) if err.kind() == ErrorKind::WouldBlock);
+        blocking_result.is_err() || !would_block
     }
 
     /**
-     * Returns true if the tcp stream is not connected.
+     * Closes the tcp stream.
      */
-    pub fn is_not_connected(&self) -> bool  {
-        !self.tcp_stream.is_some()
+    pub fn close(&mut self) {
+        if let Some(tcp_stream) = self.tcp_stream.take() {
+            let _ = tcp_stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /**
+     * Returns the connected tcp stream.
+     * Used by connections layered on top of the tcp connection.
+     */
+    pub fn get_tcp_stream(&mut self) -> Result<&mut TcpStream, ClientError> {
+        match &mut self.tcp_stream {
+            None => Err(ClientError::new(ClientErrorType::NoAvailableTcpStream, "Could not retrieve tcp stream".to_string())),
+            Some(tcp_stream) => Ok(tcp_stream)
+        }
     }
 
     /**
      * Returns a string with the host and port.
+     * An IPv6 address is enclosed in brackets, as the host of an url, if it is not already.
+     * Example: [::1]:8080
      */
     fn get_connect_str(&self) -> String {
         let mut connect_str = String::new();
-        connect_str.push_str(&self.host);
+        if self.host.contains(':') && !self.host.starts_with('[') {
+            connect_str.push('[');
+            connect_str.push_str(&self.host);
+            connect_str.push(']');
+        } else {
+            connect_str.push_str(&self.host);
+        }
         connect_str.push(':');
         connect_str.push_str(&self.port.to_string());
         connect_str
@@ -109,6 +189,41 @@ impl TcpConnection {
 
 }
 
+impl Connection for TcpConnection {
+    fn connect(&mut self) -> Result<(), ClientError> {
+        TcpConnection::connect(self)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), ClientError> {
+        TcpConnection::write(self, data)
+    }
+
+    fn is_not_connected(&self) -> bool {
+        TcpConnection::is_not_connected(self)
+    }
+
+    fn is_stale(&mut self) -> bool {
+        TcpConnection::is_stale(self)
+    }
+
+    fn close(&mut self) {
+        TcpConnection::close(self)
+    }
+}
+
+/**
+ * Reads data from the tcp stream.
+ * Returns an error of kind NotConnected if the tcp stream is not connected.
+ */
+impl Read for TcpConnection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match &mut self.tcp_stream {
+            None => Err(std::io::Error::new(ErrorKind::NotConnected, "Could not retrieve tcp stream")),
+            Some(tcp_stream) => tcp_stream.read(buf)
+        }
+    }
+}
+
 
 #[cfg(test)]
 mod test {
@@ -118,17 +233,61 @@ mod test {
     fn test_get_connect_str() {
         let tcp_connection = TcpConnection::new("localhost".to_string(), 8080, Duration::from_secs(5));
         assert_eq!(tcp_connection.get_connect_str(), "localhost:8080");
+        let tcp_connection = TcpConnection::new("::1".to_string(), 8080, Duration::from_secs(5));
+        assert_eq!(tcp_connection.get_connect_str(), "[::1]:8080");
+        let tcp_connection = TcpConnection::new("[::1]".to_string(), 8080, Duration::from_secs(5));
+        assert_eq!(tcp_connection.get_connect_str(), "[::1]:8080");
+    }
+
+    #[test]
+    fn test_get_tcp_stream_not_connected() {
+        let mut tcp_connection = TcpConnection::new("localhost".to_string(), 8080, Duration::from_secs(5));
+        let error = tcp_connection.get_tcp_stream().err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::NoAvailableTcpStream);
+    }
+
+    #[test]
+    fn test_is_stale() {
+        let tcp_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = tcp_listener.local_addr().unwrap().port();
+        let mut tcp_connection = TcpConnection::new("127.0.0.1".to_string(), port, Duration::from_secs(5));
+        assert!(tcp_connection.is_stale());
+        tcp_connection.connect().unwrap();
+        let (mut server_stream, _) = tcp_listener.accept().unwrap();
+        assert!(!tcp_connection.is_stale());
+        server_stream.write_all(b"unexpected").unwrap();
+        // Blocks until the data is received
+        assert_eq!(tcp_connection.get_tcp_stream().unwrap().peek(&mut [0u8; 1]).unwrap(), 1);
+        assert!(tcp_connection.is_stale());
+        let mut buffer = [0u8; 16];
+        let _ = tcp_connection.read(&mut buffer).unwrap();
+        assert!(!tcp_connection.is_stale());
+        drop(server_stream);
+        // Blocks until the end of the stream is received
+        assert_eq!(tcp_connection.get_tcp_stream().unwrap().peek(&mut [0u8; 1]).unwrap(), 0);
+        assert!(tcp_connection.is_stale());
+        tcp_connection.close();
+        assert!(tcp_connection.is_not_connected());
+    }
+
+    #[test]
+    fn test_read_not_connected() {
+        let mut tcp_connection = TcpConnection::new("localhost".to_string(), 8080, Duration::from_secs(5));
+        let mut buffer = [0u8; 16];
+        let error = tcp_connection.read(&mut buffer).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::NotConnected);
     }
 
     #[test]
     #[cfg_attr(not(feature = "network"), ignore)]
     fn test_is_not_connected() {
         let mut tcp_connection = TcpConnection::new("localhost".to_string(), 80, Duration::from_secs(5));
-        assert_eq!(tcp_connection.is_not_connected(), true);
+        assert!(tcp_connection.is_not_connected());
         tcp_connection.connect().unwrap();
-        assert_eq!(tcp_connection.is_not_connected(), false);
-        tcp_connection.write(&"GET / HTTP/1.1\r\n\r\n".to_string()).unwrap();
-        tcp_connection.read().unwrap();
+        assert!(!tcp_connection.is_not_connected());
+        tcp_connection.write(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut buffer = [0u8; 1024];
+        assert!(tcp_connection.read(&mut buffer).unwrap() > 0);
     }
 
 
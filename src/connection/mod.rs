@@ -1,4 +1,19 @@
+#[allow(clippy::module_inception)]
 mod connection;
+mod tls;
+mod tls_info;
+mod tls_settings;
+mod client_hello;
+mod scan;
+mod verifier;
+mod pool;
+#[cfg(test)]
+pub(crate) mod test_tls;
 
-pub use connection::TcpConnection;
-
+pub use connection::{ Connection, TcpConnection };
+pub use tls::TlsConnection;
+pub use tls_info::{ CertificateInfo, TlsInfo };
+pub use tls_settings::{ parse_certificates, parse_cipher_suites, parse_kx_groups, parse_signature_schemes, CertType, ClientCertificate, TlsSettings, TlsVersion };
+pub use verifier::PublicKeyPin;
+pub use scan::{ Preference, ScanReport, TlsScanner };
+pub use pool::{ ConnectionPool, PoolKey, PoolSettings, PooledClient };
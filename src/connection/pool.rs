@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use std::ops::{ Deref, DerefMut };
+use std::sync::{ Arc, Condvar, Mutex, MutexGuard };
+use std::time::{ Duration, Instant };
+
+use rustls::ClientConfig;
+use url::Url;
+
+use crate::common::{ ClientError, ClientErrorType };
+use crate::connection::{ Connection, TcpConnection, TlsConnection };
+use crate::http::HttpClient;
+
+/**
+ * Origin the pooled connections are connected to.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    pub scheme: String,
+    pub host: String,
+    pub port: u16
+}
+
+impl PoolKey {
+    /**
+     * Creates a pool key. Scheme and host are compared case insensitive.
+     */
+    pub fn new(scheme: &str, host: &str, port: u16) -> PoolKey {
+        PoolKey {
+            scheme: scheme.to_ascii_lowercase(),
+            host: host.to_ascii_lowercase(),
+            port
+        }
+    }
+
+    /**
+     * Creates a pool key from the scheme, host and port of an url.
+     * Returns an error if the url has no host or the scheme is not supported.
+     */
+    pub fn from_url(url: &Url) -> Result<PoolKey, ClientError> {
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(ClientError::new(ClientErrorType::UnsupportedScheme, "Unsupported scheme".to_string()));
+        }
+        let host = match url.host_str() {
+            Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+            None => return Err(ClientError::new(ClientErrorType::UnparseableUrl, "Url has no host".to_string()))
+        };
+        Ok(PoolKey::new(url.scheme(), host, url.port_or_known_default().unwrap_or(80)))
+    }
+}
+
+/**
+ * Settings for the connection pool.
+ * max_idle is the maximum number of idle connections kept in total, max_per_host the maximum
+ * number of connections, idle and in use, to one origin, and idle_timeout how long an idle
+ * connection is kept before it is closed.
+ */
+#[derive(Clone, Debug)]
+pub struct PoolSettings {
+    pub max_idle: usize,
+    pub max_per_host: usize,
+    pub idle_timeout: Duration,
+    pub connection_timeout: Duration
+}
+
+impl Default for PoolSettings {
+    fn default() -> PoolSettings {
+        PoolSettings {
+            max_idle: 32,
+            max_per_host: 8,
+            idle_timeout: Duration::from_secs(90),
+            connection_timeout: Duration::from_millis(1000)
+        }
+    }
+}
+
+struct IdleConnection {
+    connection: Box<dyn Connection>,
+    idle_since: Instant
+}
+
+struct PoolState {
+    idle: HashMap<PoolKey, Vec<IdleConnection>>,
+    in_use: HashMap<PoolKey, usize>
+}
+
+impl PoolState {
+    fn get_idle_count(&self) -> usize {
+        self.idle.values().map(Vec::len).sum()
+    }
+
+    fn get_host_count(&self, pool_key: &PoolKey) -> usize {
+        self.idle.get(pool_key).map_or(0, Vec::len) + self.in_use.get(pool_key).copied().unwrap_or(0)
+    }
+
+    /**
+     * Closes idle connections that have been idle longer than the idle timeout.
+     */
+    fn remove_expired(&mut self, idle_timeout: Duration) {
+        for idle_connections in self.idle.values_mut() {
+            idle_connections.retain_mut(|idle_connection| {
+                let expired = idle_connection.idle_since.elapsed() >= idle_timeout;
+                if expired {
+                    idle_connection.connection.close();
+                }
+                !expired
+            });
+        }
+        self.idle.retain(|_, idle_connections| !idle_connections.is_empty());
+    }
+
+    /**
+     * Closes the connection that has been idle the longest.
+     */
+    fn remove_oldest(&mut self) {
+        let oldest = self.idle.iter()
+            .flat_map(|(pool_key, idle_connections)| idle_connections.iter().enumerate().map(move |(index, idle_connection)| (pool_key, index, idle_connection.idle_since)))
+            .min_by_key(|oldest| oldest.2)
+            .map(|oldest| (oldest.0.clone(), oldest.1));
+        if let Some((pool_key, index)) = oldest {
+            if let Some(idle_connections) = self.idle.get_mut(&pool_key) {
+                idle_connections.remove(index).connection.close();
+                if idle_connections.is_empty() {
+                    self.idle.remove(&pool_key);
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Pool of connections shared between threads.
+ * Connections are handed out per origin as http clients, and returned to the pool when the
+ * client is dropped. Idle connections are reused, most recently used first.
+ */
+pub struct ConnectionPool {
+    pool_settings: PoolSettings,
+    client_config: Arc<ClientConfig>,
+    state: Mutex<PoolState>,
+    returned: Condvar
+}
+
+impl ConnectionPool {
+    /**
+     * Creates a connection pool. The client configuration is used for https connections.
+     */
+    pub fn new(pool_settings: PoolSettings, client_config: Arc<ClientConfig>) -> Arc<ConnectionPool> {
+        Arc::new(ConnectionPool {
+            pool_settings,
+            client_config,
+            state: Mutex::new(PoolState { idle: HashMap::new(), in_use: HashMap::new() }),
+            returned: Condvar::new()
+        })
+    }
+
+    /**
+     * Get a http client for the origin.
+     * An idle connection is reused if available. Otherwise a new connection is created, waiting
+     * for a connection to be returned if the origin already has the maximum number of connections.
+     * Returns an error if no connection is returned within the connection timeout.
+     */
+    pub fn get(self: &Arc<Self>, pool_key: &PoolKey) -> Result<PooledClient, ClientError> {
+        let mut state = self.lock_state();
+        let deadline = Instant::now() + self.pool_settings.connection_timeout;
+        loop {
+            state.remove_expired(self.pool_settings.idle_timeout);
+            let idle_connection = state.idle.get_mut(pool_key).and_then(Vec::pop);
+            if let Some(idle_connection) = idle_connection {
+                *state.in_use.entry(pool_key.clone()).or_insert(0) += 1;
+                return Ok(self.get_pooled_client(pool_key, idle_connection.connection));
+            }
+            if state.get_host_count(pool_key) < self.pool_settings.max_per_host {
+                *state.in_use.entry(pool_key.clone()).or_insert(0) += 1;
+                drop(state);
+                let connection = self.create_connection(pool_key);
+                return Ok(self.get_pooled_client(pool_key, connection));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ClientError::new(ClientErrorType::PoolTimeout, format!("No connection to {}:{} available", pool_key.host, pool_key.port)));
+            }
+            state = match self.returned.wait_timeout(state, deadline - now) {
+                Ok((state, _)) => state,
+                Err(poisoned) => poisoned.into_inner().0
+            };
+        }
+    }
+
+    /**
+     * Returns the number of idle connections in the pool.
+     */
+    pub fn get_idle_count(&self) -> usize {
+        self.lock_state().get_idle_count()
+    }
+
+    /**
+     * Closes all idle connections.
+     */
+    pub fn clear(&self) {
+        let mut state = self.lock_state();
+        state.idle.values_mut().flatten().for_each(|idle_connection| idle_connection.connection.close());
+        state.idle.clear();
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, PoolState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner()
+        }
+    }
+
+    fn create_connection(&self, pool_key: &PoolKey) -> Box<dyn Connection> {
+        let tcp_connection = TcpConnection::new(pool_key.host.clone(), pool_key.port, self.pool_settings.connection_timeout);
+        if pool_key.scheme == "https" {
+            Box::new(TlsConnection::new(tcp_connection, pool_key.host.clone(), self.client_config.clone()))
+        } else {
+            Box::new(tcp_connection)
+        }
+    }
+
+    fn get_pooled_client(self: &Arc<Self>, pool_key: &PoolKey, connection: Box<dyn Connection>) -> PooledClient {
+        PooledClient {
+            pool: self.clone(),
+            pool_key: pool_key.clone(),
+            http_client: Some(HttpClient::from_connection(connection))
+        }
+    }
+
+    /**
+     * Returns a connection to the pool. Closed connections are discarded, and the
+     * oldest idle connection is closed if the pool has the maximum number of idle connections.
+     */
+    fn put(&self, pool_key: &PoolKey, mut connection: Box<dyn Connection>) {
+        let mut state = self.lock_state();
+        if let Some(in_use) = state.in_use.get_mut(pool_key) {
+            *in_use = in_use.saturating_sub(1);
+        }
+        if !connection.is_not_connected() && self.pool_settings.max_idle > 0 {
+            if state.get_idle_count() >= self.pool_settings.max_idle {
+                state.remove_oldest();
+            }
+            state.idle.entry(pool_key.clone()).or_default().push(IdleConnection { connection, idle_since: Instant::now() });
+        } else {
+            connection.close();
+        }
+        drop(state);
+        self.returned.notify_all();
+    }
+}
+
+/**
+ * Http client with a connection from the pool.
+ * The connection is returned to the pool when the client is dropped.
+ */
+pub struct PooledClient {
+    pool: Arc<ConnectionPool>,
+    pool_key: PoolKey,
+    http_client: Option<HttpClient>
+}
+
+impl Deref for PooledClient {
+    type Target = HttpClient;
+
+    fn deref(&self) -> &HttpClient {
+        self.http_client.as_ref().expect("Http client is available until dropped")
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut HttpClient {
+        self.http_client.as_mut().expect("Http client is available until dropped")
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        if let Some(http_client) = self.http_client.take() {
+            self.pool.put(&self.pool_key, http_client.into_connection());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{ BufRead, BufReader, Write };
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use crate::http::{ HeaderMap, HttpRequest };
+
+    const RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+
+    /**
+     * Starts a server answering every request on every accepted connection.
+     * Returns the port and a counter with the number of accepted connections.
+     */
+    fn start_server() -> (u16, Arc<Mutex<usize>>) {
+        start_server_on(TcpListener::bind("127.0.0.1:0").unwrap())
+    }
+
+    fn start_server_on(tcp_listener: TcpListener) -> (u16, Arc<Mutex<usize>>) {
+        let port = tcp_listener.local_addr().unwrap().port();
+        let accepted = Arc::new(Mutex::new(0));
+        let server_accepted = accepted.clone();
+        thread::spawn(move || {
+            for tcp_stream in tcp_listener.incoming() {
+                let mut tcp_stream = tcp_stream.unwrap();
+                *server_accepted.lock().unwrap() += 1;
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(tcp_stream.try_clone().unwrap());
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                        if line == "\r\n" {
+                            tcp_stream.write_all(RESPONSE.as_bytes()).unwrap();
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+        (port, accepted)
+    }
+
+    fn get_pool(pool_settings: PoolSettings) -> Arc<ConnectionPool> {
+        ConnectionPool::new(pool_settings, TlsConnection::get_client_config().unwrap())
+    }
+
+    fn send(pooled_client: &mut PooledClient) {
+        let http_request = HttpRequest::new("/".to_string(), "GET".to_string(), "localhost".to_string(), HeaderMap::new(), None);
+        assert_eq!(pooled_client.send(http_request).unwrap().body, b"ok");
+    }
+
+    #[test]
+    fn test_pool_key_from_url() {
+        let pool_key = PoolKey::from_url(&Url::parse("https://Example.COM/path").unwrap()).unwrap();
+        assert_eq!(pool_key, PoolKey::new("https", "example.com", 443));
+        let pool_key = PoolKey::from_url(&Url::parse("http://[::1]:8080/").unwrap()).unwrap();
+        assert_eq!(pool_key, PoolKey::new("http", "::1", 8080));
+        let error = PoolKey::from_url(&Url::parse("ftp://example.com/").unwrap()).err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::UnsupportedScheme);
+    }
+
+    #[test]
+    fn test_get_reuses_idle_connection() {
+        let (port, accepted) = start_server();
+        let pool = get_pool(PoolSettings::default());
+        let pool_key = PoolKey::new("http", "127.0.0.1", port);
+        for _ in 0..3 {
+            let mut pooled_client = pool.get(&pool_key).unwrap();
+            send(&mut pooled_client);
+        }
+        assert_eq!(pool.get_idle_count(), 1);
+        assert_eq!(*accepted.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_get_ipv6_origin() {
+        let pool_key = PoolKey::from_url(&Url::parse("http://[::1]:8080/").unwrap()).unwrap();
+        assert_eq!(pool_key, PoolKey::new("http", "::1", 8080));
+        // Skipping the connection when ipv6 is not available
+        let tcp_listener = match TcpListener::bind("[::1]:0") {
+            Ok(tcp_listener) => tcp_listener,
+            Err(_) => return
+        };
+        let (port, accepted) = start_server_on(tcp_listener);
+        let pool = get_pool(PoolSettings::default());
+        let pool_key = PoolKey::from_url(&Url::parse(&format!("http://[::1]:{}/", port)).unwrap()).unwrap();
+        send(&mut pool.get(&pool_key).unwrap());
+        assert_eq!(*accepted.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_get_idle_timeout() {
+        let (port, accepted) = start_server();
+        // Idle connections expire immediately
+        let pool = get_pool(PoolSettings { idle_timeout: Duration::ZERO, ..PoolSettings::default() });
+        let pool_key = PoolKey::new("http", "127.0.0.1", port);
+        send(&mut pool.get(&pool_key).unwrap());
+        send(&mut pool.get(&pool_key).unwrap());
+        assert_eq!(*accepted.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_put_max_idle() {
+        let (port, _) = start_server();
+        let pool = get_pool(PoolSettings { max_idle: 1, ..PoolSettings::default() });
+        let pool_key = PoolKey::new("http", "127.0.0.1", port);
+        let mut first_client = pool.get(&pool_key).unwrap();
+        let mut second_client = pool.get(&pool_key).unwrap();
+        send(&mut first_client);
+        send(&mut second_client);
+        drop(first_client);
+        drop(second_client);
+        assert_eq!(pool.get_idle_count(), 1);
+        pool.clear();
+        assert_eq!(pool.get_idle_count(), 0);
+    }
+
+    #[test]
+    fn test_get_max_per_host() {
+        let (port, accepted) = start_server();
+        let pool = get_pool(PoolSettings { max_per_host: 1, connection_timeout: Duration::from_secs(1), ..PoolSettings::default() });
+        let pool_key = PoolKey::new("http", "127.0.0.1", port);
+        let mut pooled_client = pool.get(&pool_key).unwrap();
+        send(&mut pooled_client);
+        let error = pool.get(&pool_key).err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::PoolTimeout);
+        let thread_pool = pool.clone();
+        let thread_pool_key = pool_key.clone();
+        let (started_sender, started_receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            started_sender.send(()).unwrap();
+            send(&mut thread_pool.get(&thread_pool_key).unwrap())
+        });
+        // The connection is returned while the thread waits for it, or before it asks for it
+        started_receiver.recv().unwrap();
+        drop(pooled_client);
+        handle.join().unwrap();
+        assert_eq!(*accepted.lock().unwrap(), 1);
+    }
+}
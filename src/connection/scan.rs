@@ -0,0 +1,496 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::client::Resumption;
+use rustls::crypto::aws_lc_rs;
+use rustls::CipherSuite;
+
+use crate::common::{ ClientError, ClientErrorType };
+use crate::connection::client_hello::{ build_client_hello, get_version_name, read_server_hello, ServerHello, SSL_3_0, TLS_1_0, TLS_1_1, TLS_1_2 };
+use crate::connection::{ parse_cipher_suites, parse_kx_groups, Connection, TcpConnection, TlsConnection, TlsInfo, TlsSettings, TlsVersion };
+
+/**
+ * ALPN protocols offered when scanning.
+ */
+const ALPN_PROTOCOLS: &[&str] = &["h2", "http/1.1", "http/1.0"];
+
+/**
+ * Versions before TLS 1.2, probed with a ClientHello as rustls does not implement them, and why
+ * they are weak.
+ */
+const LEGACY_VERSIONS: &[(u16, &str)] = &[
+    (TLS_1_1, "deprecated by RFC 8996"),
+    (TLS_1_0, "deprecated by RFC 8996"),
+    (SSL_3_0, "prohibited by RFC 7568"),
+];
+
+/**
+ * Weak cipher suites, probed with a ClientHello as rustls does not implement them, with their
+ * name, as rustls does not display most of them, and why they are weak.
+ */
+const WEAK_CIPHER_SUITES: &[(u16, &str, &str)] = &[
+    (0x0001, "TLS_RSA_WITH_NULL_MD5", "no encryption"),
+    (0x0002, "TLS_RSA_WITH_NULL_SHA", "no encryption"),
+    (0x003b, "TLS_RSA_WITH_NULL_SHA256", "no encryption"),
+    (0x0003, "TLS_RSA_EXPORT_WITH_RC4_40_MD5", "export grade encryption"),
+    (0x0006, "TLS_RSA_EXPORT_WITH_RC2_CBC_40_MD5", "export grade encryption"),
+    (0x0008, "TLS_RSA_EXPORT_WITH_DES40_CBC_SHA", "export grade encryption"),
+    (0x0014, "TLS_DHE_RSA_EXPORT_WITH_DES40_CBC_SHA", "export grade encryption"),
+    (0x0018, "TLS_DH_anon_WITH_RC4_128_MD5", "anonymous key exchange without authentication"),
+    (0x0034, "TLS_DH_anon_WITH_AES_128_CBC_SHA", "anonymous key exchange without authentication"),
+    (0xc018, "TLS_ECDH_anon_WITH_AES_128_CBC_SHA", "anonymous key exchange without authentication"),
+    (0x0004, "TLS_RSA_WITH_RC4_128_MD5", "RC4 is prohibited by RFC 7465"),
+    (0x0005, "TLS_RSA_WITH_RC4_128_SHA", "RC4 is prohibited by RFC 7465"),
+    (0xc007, "TLS_ECDHE_ECDSA_WITH_RC4_128_SHA", "RC4 is prohibited by RFC 7465"),
+    (0xc011, "TLS_ECDHE_RSA_WITH_RC4_128_SHA", "RC4 is prohibited by RFC 7465"),
+    (0x0009, "TLS_RSA_WITH_DES_CBC_SHA", "DES has a 56 bit key"),
+    (0x000a, "TLS_RSA_WITH_3DES_EDE_CBC_SHA", "3DES has a 64 bit block size"),
+    (0xc008, "TLS_ECDHE_ECDSA_WITH_3DES_EDE_CBC_SHA", "3DES has a 64 bit block size"),
+    (0xc012, "TLS_ECDHE_RSA_WITH_3DES_EDE_CBC_SHA", "3DES has a 64 bit block size"),
+    (0x002f, "TLS_RSA_WITH_AES_128_CBC_SHA", "RSA key exchange without forward secrecy"),
+    (0x0035, "TLS_RSA_WITH_AES_256_CBC_SHA", "RSA key exchange without forward secrecy"),
+    (0x003c, "TLS_RSA_WITH_AES_128_CBC_SHA256", "RSA key exchange without forward secrecy"),
+    (0x003d, "TLS_RSA_WITH_AES_256_CBC_SHA256", "RSA key exchange without forward secrecy"),
+    (0x009c, "TLS_RSA_WITH_AES_128_GCM_SHA256", "RSA key exchange without forward secrecy"),
+    (0x009d, "TLS_RSA_WITH_AES_256_GCM_SHA384", "RSA key exchange without forward secrecy"),
+    (0x0033, "TLS_DHE_RSA_WITH_AES_128_CBC_SHA", "CBC mode with MAC then encrypt"),
+    (0x0039, "TLS_DHE_RSA_WITH_AES_256_CBC_SHA", "CBC mode with MAC then encrypt"),
+    (0xc009, "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA", "CBC mode with MAC then encrypt"),
+    (0xc00a, "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA", "CBC mode with MAC then encrypt"),
+    (0xc013, "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA", "CBC mode with MAC then encrypt"),
+    (0xc014, "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA", "CBC mode with MAC then encrypt"),
+    (0xc023, "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256", "CBC mode with MAC then encrypt"),
+    (0xc024, "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA384", "CBC mode with MAC then encrypt"),
+    (0xc027, "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256", "CBC mode with MAC then encrypt"),
+    (0xc028, "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384", "CBC mode with MAC then encrypt"),
+];
+
+/**
+ * Options accepted by the server, in the order the server chooses them.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Preference {
+    pub accepted: Vec<String>,
+    // True if the server chooses by its own preference, false if it follows the order of the
+    // client. None if fewer than two options are accepted.
+    pub server_preference: Option<bool>
+}
+
+impl fmt::Display for Preference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.server_preference {
+            Some(true) => write!(f, "server preference")?,
+            Some(false) => write!(f, "client preference")?,
+            None if self.accepted.is_empty() => write!(f, "none")?,
+            None => {}
+        }
+        for accepted in &self.accepted {
+            write!(f, "\n  {}", accepted)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Result of a scan: the protocol versions, cipher suites, key exchange groups and ALPN protocols
+ * the server accepts, and the weak options among them.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanReport {
+    // Example localhost:443
+    pub target: String,
+    // Error of a handshake with the tls settings, None if the server certificate is verified.
+    pub verification_error: Option<String>,
+    // Protocol versions from TLSv1.3 to SSLv3, and if they are accepted.
+    pub protocol_versions: Vec<(String, bool)>,
+    // Cipher suites of each accepted protocol version.
+    pub cipher_suites: Vec<(String, Preference)>,
+    // Key exchange groups of the highest protocol version rustls implements.
+    pub kx_groups: Preference,
+    pub alpn_protocols: Preference,
+    // Example TLSv1.0 is accepted: deprecated by RFC 8996
+    pub weak_options: Vec<String>
+}
+
+/**
+ * Displays the scan report, one section per line, followed by the accepted options.
+ */
+impl fmt::Display for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Target : {}", self.target)?;
+        match &self.verification_error {
+            Some(verification_error) => writeln!(f, "Certificate verification : failed, {}", verification_error)?,
+            None => writeln!(f, "Certificate verification : ok")?
+        }
+        write!(f, "Protocol versions :")?;
+        for (version, accepted) in &self.protocol_versions {
+            write!(f, "\n  {} : {}", version, if *accepted { "accepted" } else { "refused" })?;
+        }
+        for (version, cipher_suites) in &self.cipher_suites {
+            write!(f, "\nCipher suites {} : {}", version, cipher_suites)?;
+        }
+        write!(f, "\nKey exchange groups : {}", self.kx_groups)?;
+        write!(f, "\nALPN protocols : {}", self.alpn_protocols)?;
+        write!(f, "\nWeak options :{}", if self.weak_options.is_empty() { " none" } else { "" })?;
+        for weak_option in &self.weak_options {
+            write!(f, "\n  {}", weak_option)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Scanner of the tls capabilities of a server.
+ * Each probe connects like TcpConnection::connect and performs a handshake with a single option
+ * changed. TLS 1.3 and TLS 1.2 are probed with complete rustls handshakes. Older versions and
+ * TLS 1.2 cipher suites are probed with a ClientHello, reading only the ServerHello, as rustls
+ * does not implement most of them.
+ * Probes do not verify the server certificate, so the server can be scanned without trusting
+ * it. The client certificate of the tls settings is presented if the server asks for one.
+ */
+pub struct TlsScanner {
+    host: String,
+    port: u16,
+    connection_timeout: Duration,
+    server_name: String,
+    tls_settings: TlsSettings
+}
+
+impl TlsScanner {
+    /**
+     * Creates a scanner of the server at the host and port.
+     * The server name is sent as SNI and used to verify the server certificate with the tls settings.
+     */
+    pub fn new(host: String, port: u16, connection_timeout: Duration, server_name: String, tls_settings: TlsSettings) -> TlsScanner {
+        TlsScanner {
+            host,
+            port,
+            connection_timeout,
+            server_name,
+            tls_settings
+        }
+    }
+
+    /**
+     * Scans the server.
+     * Returns an error if the server cannot be connected to, or answers a probe with something
+     * other than tls.
+     */
+    pub fn scan(&self) -> Result<ScanReport, ClientError> {
+        let verification_error = match self.handshake(&self.tls_settings, &[]) {
+            Ok(_) => None,
+            Err(err) if matches!(err.error_type, ClientErrorType::TlsError | ClientErrorType::PinMismatch) => Some(err.message),
+            Err(err) => return Err(err)
+        };
+        let mut protocol_versions: Vec<(String, bool)> = Vec::new();
+        let mut cipher_suites: Vec<(String, Preference)> = Vec::new();
+        let mut weak_options: Vec<String> = Vec::new();
+        let tls_1_3 = self.probe(self.get_probe_settings(TlsVersion::Tls13), &[])?.is_some();
+        protocol_versions.push((get_tls_version_name(TlsVersion::Tls13), tls_1_3));
+        if tls_1_3 {
+            cipher_suites.push((get_tls_version_name(TlsVersion::Tls13), self.scan_tls_1_3_cipher_suites()?));
+        }
+        let tls_1_2 = self.probe(self.get_probe_settings(TlsVersion::Tls12), &[])?.is_some();
+        protocol_versions.push((get_tls_version_name(TlsVersion::Tls12), tls_1_2));
+        if tls_1_2 {
+            cipher_suites.push((get_version_name(TLS_1_2), self.scan_cipher_suites(TLS_1_2, &mut weak_options)?));
+        }
+        for (version, reason) in LEGACY_VERSIONS {
+            let accepted = matches!(self.probe_hello(*version, &get_legacy_cipher_suites())?, Some(server_hello) if server_hello.version == *version);
+            protocol_versions.push((get_version_name(*version), accepted));
+            if accepted {
+                weak_options.push(format!("{} is accepted: {}", get_version_name(*version), reason));
+                cipher_suites.push((get_version_name(*version), self.scan_cipher_suites(*version, &mut weak_options)?));
+            }
+        }
+        let version = if tls_1_3 { Some(TlsVersion::Tls13) } else if tls_1_2 { Some(TlsVersion::Tls12) } else { None };
+        let (kx_groups, alpn_protocols) = match version {
+            Some(version) => (self.scan_kx_groups(version)?, self.scan_alpn_protocols(version)?),
+            None => (Preference::default(), Preference::default())
+        };
+        Ok(ScanReport {
+            target: format!("{}:{}", self.host, self.port),
+            verification_error,
+            protocol_versions,
+            cipher_suites,
+            kx_groups,
+            alpn_protocols,
+            weak_options
+        })
+    }
+
+    fn scan_tls_1_3_cipher_suites(&self) -> Result<Preference, ClientError> {
+        let names: Vec<String> = aws_lc_rs::ALL_CIPHER_SUITES
+            .iter()
+            .filter(|cipher_suite| cipher_suite.version() == TlsVersion::Tls13.get_protocol_version())
+            .map(|cipher_suite| format!("{:?}", cipher_suite.suite()))
+            .collect();
+        get_preference(&names, |names| {
+            let tls_settings = self.get_probe_settings(TlsVersion::Tls13).with_cipher_suites(parse_cipher_suites(names)?);
+            Ok(self.probe(tls_settings, &[])?.map(|tls_info| tls_info.cipher_suite))
+        })
+    }
+
+    /**
+     * Scans the cipher suites of a version before TLS 1.3 with ClientHello probes. The weak
+     * cipher suites accepted are added to the weak options.
+     */
+    fn scan_cipher_suites(&self, version: u16, weak_options: &mut Vec<String>) -> Result<Preference, ClientError> {
+        let preference = get_preference(&get_legacy_cipher_suites(), |cipher_suites| {
+            match self.probe_hello(version, cipher_suites)? {
+                Some(server_hello) if server_hello.version == version => Ok(Some(server_hello.cipher_suite)),
+                _ => Ok(None)
+            }
+        })?;
+        for (_, name, reason) in WEAK_CIPHER_SUITES.iter().filter(|(_, name, _)| preference.accepted.iter().any(|accepted| accepted == name)) {
+            let weak_option = format!("{} is accepted: {}", name, reason);
+            if !weak_options.contains(&weak_option) {
+                weak_options.push(weak_option);
+            }
+        }
+        Ok(preference)
+    }
+
+    fn scan_kx_groups(&self, version: TlsVersion) -> Result<Preference, ClientError> {
+        let names: Vec<String> = aws_lc_rs::ALL_KX_GROUPS
+            .iter()
+            .filter(|kx_group| kx_group.usable_for_version(version.get_protocol_version().version))
+            .map(|kx_group| format!("{:?}", kx_group.name()))
+            .collect();
+        get_preference(&names, |names| {
+            let tls_settings = self.get_probe_settings(version).with_kx_groups(parse_kx_groups(names)?);
+            Ok(self.probe(tls_settings, &[])?.and_then(|tls_info| tls_info.key_exchange_group))
+        })
+    }
+
+    fn scan_alpn_protocols(&self, version: TlsVersion) -> Result<Preference, ClientError> {
+        let names: Vec<String> = ALPN_PROTOCOLS.iter().map(|name| name.to_string()).collect();
+        get_preference(&names, |names| {
+            Ok(self.probe(self.get_probe_settings(version), names)?.and_then(|tls_info| tls_info.alpn_protocol))
+        })
+    }
+
+    /**
+     * Get the settings of a probe with a single protocol version. The server certificate is not
+     * verified and the client certificate is presented.
+     */
+    fn get_probe_settings(&self, version: TlsVersion) -> TlsSettings {
+        TlsSettings::default()
+            .with_client_certificate(self.tls_settings.client_certificate.clone())
+            .with_insecure(true)
+            .with_versions(Some(version), Some(version))
+    }
+
+    /**
+     * Performs a handshake probe. Returns None if the handshake fails.
+     */
+    fn probe(&self, tls_settings: TlsSettings, alpn_protocols: &[String]) -> Result<Option<TlsInfo>, ClientError> {
+        match self.handshake(&tls_settings, alpn_protocols) {
+            Ok(tls_info) => Ok(Some(tls_info)),
+            Err(err) if err.error_type == ClientErrorType::TlsError => Ok(None),
+            Err(err) => Err(err)
+        }
+    }
+
+    /**
+     * Performs a complete handshake without session resumption and returns the tls info.
+     * The ALPN protocols are offered instead of http/1.1 if there are any.
+     */
+    fn handshake(&self, tls_settings: &TlsSettings, alpn_protocols: &[String]) -> Result<TlsInfo, ClientError> {
+        let mut client_config = TlsConnection::get_client_config_with(tls_settings)?.as_ref().clone();
+        client_config.resumption = Resumption::disabled();
+        if !alpn_protocols.is_empty() {
+            client_config.alpn_protocols = alpn_protocols.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+        }
+        let tcp_connection = TcpConnection::new(self.host.clone(), self.port, self.connection_timeout);
+        let mut tls_connection = TlsConnection::new(tcp_connection, self.server_name.clone(), Arc::new(client_config));
+        let result = tls_connection.connect();
+        let tls_info = tls_connection.get_tls_info();
+        tls_connection.close();
+        result?;
+        match tls_info {
+            Some(tls_info) => Ok(tls_info),
+            None => Err(ClientError::new(ClientErrorType::TlsError, "Tls handshake is not complete".to_string()))
+        }
+    }
+
+    /**
+     * Sends a ClientHello with the version and cipher suites and reads the ServerHello.
+     * Returns None if the server refuses the hello.
+     */
+    fn probe_hello(&self, version: u16, cipher_suites: &[u16]) -> Result<Option<ServerHello>, ClientError> {
+        let server_name = match self.server_name.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            Ok(_) => None,
+            Err(_) => Some(self.server_name.as_str())
+        };
+        let client_hello = build_client_hello(version, cipher_suites, server_name)?;
+        let mut tcp_connection = TcpConnection::new(self.host.clone(), self.port, self.connection_timeout);
+        tcp_connection.connect()?;
+        let server_hello = match tcp_connection.write(&client_hello) {
+            Ok(_) => read_server_hello(tcp_connection.get_tcp_stream()?),
+            Err(_) => Ok(None)
+        };
+        tcp_connection.close();
+        server_hello
+    }
+}
+
+/**
+ * Get the order in which the server chooses among the candidates, by offering the candidates
+ * not chosen yet until the server refuses them. The choose function returns the option chosen
+ * by the server, or None if the server refuses the options.
+ * The accepted options are offered again in reverse order: if the server chooses the same option
+ * first, it chooses by its own preference.
+ */
+fn get_preference<T: Clone + PartialEq + ToName>(candidates: &[T], mut choose: impl FnMut(&[T]) -> Result<Option<T>, ClientError>) -> Result<Preference, ClientError> {
+    let mut remaining: Vec<T> = candidates.to_vec();
+    let mut accepted: Vec<T> = Vec::new();
+    while !remaining.is_empty() {
+        match choose(&remaining)? {
+            Some(chosen) if remaining.contains(&chosen) => {
+                remaining.retain(|candidate| *candidate != chosen);
+                accepted.push(chosen);
+            },
+            _ => break
+        }
+    }
+    let server_preference = match accepted.len() {
+        0 | 1 => None,
+        _ => {
+            let reversed: Vec<T> = accepted.iter().rev().cloned().collect();
+            Some(choose(&reversed)?.as_ref() == accepted.first())
+        }
+    };
+    Ok(Preference {
+        accepted: accepted.iter().map(ToName::to_name).collect(),
+        server_preference
+    })
+}
+
+/**
+ * Name of a scanned option in the report.
+ */
+trait ToName {
+    fn to_name(&self) -> String;
+}
+
+impl ToName for String {
+    fn to_name(&self) -> String {
+        self.clone()
+    }
+}
+
+impl ToName for u16 {
+    fn to_name(&self) -> String {
+        get_cipher_suite_name(*self)
+    }
+}
+
+/**
+ * Get the cipher suites offered when probing versions before TLS 1.3: the TLS 1.2 cipher suites
+ * of rustls followed by the weak cipher suites.
+ */
+fn get_legacy_cipher_suites() -> Vec<u16> {
+    aws_lc_rs::ALL_CIPHER_SUITES
+        .iter()
+        .filter(|cipher_suite| cipher_suite.version() == TlsVersion::Tls12.get_protocol_version())
+        .map(|cipher_suite| u16::from(cipher_suite.suite()))
+        .chain(WEAK_CIPHER_SUITES.iter().map(|(code, _, _)| *code))
+        .collect()
+}
+
+fn get_cipher_suite_name(code: u16) -> String {
+    match WEAK_CIPHER_SUITES.iter().find(|(weak_code, _, _)| *weak_code == code) {
+        Some((_, name, _)) => name.to_string(),
+        None => format!("{:?}", CipherSuite::from(code))
+    }
+}
+
+fn get_tls_version_name(version: TlsVersion) -> String {
+    format!("{:?}", version.get_protocol_version().version).replace('_', ".")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::connection::test_tls::{ get_certificate, get_server_config, start_server, CA_CERTIFICATE };
+
+    const RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+
+    fn get_scanner(port: u16, tls_settings: TlsSettings) -> TlsScanner {
+        TlsScanner::new("127.0.0.1".to_string(), port, Duration::from_secs(5), "localhost".to_string(), tls_settings)
+    }
+
+    #[test]
+    fn test_get_preference() {
+        let candidates: Vec<String> = ["a", "b", "c"].iter().map(|name| name.to_string()).collect();
+        let server_order = ["c", "a"];
+        let preference = get_preference(&candidates, |offered| {
+            Ok(server_order.iter().map(|name| name.to_string()).find(|name| offered.contains(name)))
+        }).unwrap();
+        assert_eq!(preference.accepted, vec!["c".to_string(), "a".to_string()]);
+        assert_eq!(preference.server_preference, Some(true));
+        let preference = get_preference(&candidates, |offered| Ok(offered.iter().find(|name| *name != "b").cloned())).unwrap();
+        assert_eq!(preference.accepted, vec!["a".to_string(), "c".to_string()]);
+        assert_eq!(preference.server_preference, Some(false));
+        let preference = get_preference(&candidates, |_| Ok(None)).unwrap();
+        assert_eq!(preference, Preference::default());
+        assert_eq!(preference.to_string(), "none");
+    }
+
+    #[test]
+    fn test_cipher_suite_names() {
+        assert_eq!(get_cipher_suite_name(0xc02b), "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256");
+        assert_eq!(get_cipher_suite_name(0x0034), "TLS_DH_anon_WITH_AES_128_CBC_SHA");
+        assert_eq!(get_tls_version_name(TlsVersion::Tls13), "TLSv1.3");
+        assert!(get_legacy_cipher_suites().starts_with(&[0xc02c]));
+    }
+
+    #[test]
+    fn test_scan() {
+        let port = start_server(get_server_config(), RESPONSE);
+        let tls_settings = TlsSettings::default().with_ca_certificates(vec![get_certificate(CA_CERTIFICATE)], false);
+        let scan_report = get_scanner(port, tls_settings).scan().unwrap();
+        assert_eq!(scan_report.target, format!("127.0.0.1:{}", port));
+        assert_eq!(scan_report.verification_error, None);
+        let expected_versions = [("TLSv1.3", true), ("TLSv1.2", true), ("TLSv1.1", false), ("TLSv1.0", false), ("SSLv3", false)];
+        assert_eq!(scan_report.protocol_versions, expected_versions.iter().map(|(version, accepted)| (version.to_string(), *accepted)).collect::<Vec<_>>());
+        assert_eq!(scan_report.cipher_suites.len(), 2);
+        let (version, tls_1_3_cipher_suites) = &scan_report.cipher_suites[0];
+        assert_eq!(version, "TLSv1.3");
+        assert_eq!(tls_1_3_cipher_suites.accepted.len(), 3);
+        assert_eq!(tls_1_3_cipher_suites.server_preference, Some(false));
+        let (version, tls_1_2_cipher_suites) = &scan_report.cipher_suites[1];
+        assert_eq!(version, "TLSv1.2");
+        assert!(!tls_1_2_cipher_suites.accepted.is_empty());
+        assert!(tls_1_2_cipher_suites.accepted.iter().all(|cipher_suite| cipher_suite.starts_with("TLS_ECDHE_ECDSA_WITH_")));
+        assert!(scan_report.kx_groups.accepted.contains(&"X25519".to_string()));
+        assert_eq!(scan_report.alpn_protocols.accepted, vec!["http/1.1".to_string()]);
+        assert!(scan_report.weak_options.is_empty());
+        let report = scan_report.to_string();
+        assert!(report.contains("Certificate verification : ok\n"));
+        assert!(report.ends_with("Weak options : none"));
+    }
+
+    #[test]
+    fn test_scan_server_preference() {
+        let mut server_config = get_server_config();
+        server_config.ignore_client_order = true;
+        let port = start_server(server_config, RESPONSE);
+        let scan_report = get_scanner(port, TlsSettings::default()).scan().unwrap();
+        assert!(scan_report.verification_error.is_some());
+        assert_eq!(scan_report.cipher_suites[0].1.server_preference, Some(true));
+        assert_eq!(scan_report.cipher_suites[1].1.server_preference, Some(true));
+    }
+
+    #[test]
+    fn test_scan_connection_refused() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let error = get_scanner(port, TlsSettings::default()).scan().err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::ConnectionFailure);
+    }
+}
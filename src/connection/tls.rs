@@ -0,0 +1,311 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use rustls::client::danger::ServerCertVerifier;
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::ServerName;
+use rustls::{ ClientConfig, ClientConnection, RootCertStore, Stream };
+
+use crate::common::{ ClientError, ClientErrorType };
+use crate::connection::connection::{ Connection, TcpConnection };
+use crate::connection::{ TlsInfo, TlsSettings };
+use crate::connection::verifier::{ get_pin_mismatch_error, InsecureVerifier, PinningVerifier, SignatureSchemeVerifier };
+
+const ALPN_HTTP_1_1: &[u8] = b"http/1.1";
+
+/**
+ * Tls connection layered on top of a tcp connection.
+ * The tcp connection is established first, then the tls handshake is performed
+ * and the server certificate is verified against the configured root certificates.
+ */
+pub struct TlsConnection {
+    tcp_connection: TcpConnection,
+    server_name: String,
+    client_config: Arc<ClientConfig>,
+    tls_connection: Option<ClientConnection>,
+    tls_info: Option<TlsInfo>
+}
+
+impl TlsConnection {
+    /**
+     * Creates a new TlsConnection over a tcp connection.
+     * The server name is sent as SNI and used to verify the server certificate.
+     */
+    pub fn new(tcp_connection: TcpConnection, server_name: String, client_config: Arc<ClientConfig>) -> TlsConnection {
+        TlsConnection {
+            tcp_connection,
+            server_name,
+            client_config,
+            tls_connection: None,
+            tls_info: None
+        }
+    }
+
+    /**
+     * Get the default client configuration.
+     * Server certificates are verified against the webpki roots and the system root certificates.
+     * http/1.1 is offered with ALPN.
+     */
+    pub fn get_client_config() -> Result<Arc<ClientConfig>, ClientError> {
+        Self::get_client_config_with(&TlsSettings::default())
+    }
+
+    /**
+     * Get the client configuration for the settings.
+     * Only the protocol versions, cipher suites, key exchange groups and signature schemes of the
+     * settings are offered. The client certificate is presented if the server asks for one.
+     * Returns an error if no cipher suite can be used with the protocol versions, or if the
+     * client certificate is not supported or does not match its key.
+     */
+    pub fn get_client_config_with(tls_settings: &TlsSettings) -> Result<Arc<ClientConfig>, ClientError> {
+        let provider = Arc::new(tls_settings.get_crypto_provider());
+        let builder = match ClientConfig::builder_with_provider(provider.clone()).with_protocol_versions(&tls_settings.get_protocol_versions()?) {
+            Ok(builder) => builder,
+            Err(err) => return Err(ClientError::new(ClientErrorType::InvalidRequest, format!("Tls settings cannot be used: {}", err)))
+        };
+        let verifier = Self::get_server_verifier(tls_settings, provider)?;
+        let builder = builder.dangerous().with_custom_certificate_verifier(verifier);
+        let mut client_config = match &tls_settings.client_certificate {
+            Some(client_certificate) => match builder.with_client_auth_cert(client_certificate.chain.clone(), client_certificate.get_key()) {
+                Ok(client_config) => client_config,
+                Err(err) => return Err(ClientError::new(ClientErrorType::InvalidKey, format!("Client certificate cannot be used: {}", err)))
+            },
+            None => builder.with_no_client_auth()
+        };
+        client_config.alpn_protocols = vec![ALPN_HTTP_1_1.to_vec()];
+        Ok(Arc::new(client_config))
+    }
+
+    /**
+     * Get the verifier of server certificates for the settings.
+     * Certificates are verified against the root store, or not at all if insecure is set.
+     * Handshake signatures are only accepted with the signature schemes of the settings.
+     * If there are pins, the public key of the server certificate is checked against them,
+     * also when insecure is set.
+     */
+    fn get_server_verifier(tls_settings: &TlsSettings, provider: Arc<CryptoProvider>) -> Result<Arc<dyn ServerCertVerifier>, ClientError> {
+        let verifier: Arc<dyn ServerCertVerifier> = if tls_settings.insecure {
+            Arc::new(InsecureVerifier::new(provider))
+        } else {
+            let root_store = Self::get_root_store(tls_settings)?;
+            match WebPkiServerVerifier::builder_with_provider(Arc::new(root_store), provider).build() {
+                Ok(verifier) => verifier,
+                Err(err) => return Err(ClientError::new(ClientErrorType::TlsError, err.to_string()))
+            }
+        };
+        let verifier: Arc<dyn ServerCertVerifier> = if tls_settings.signature_schemes.is_empty() {
+            verifier
+        } else {
+            Arc::new(SignatureSchemeVerifier::new(verifier, tls_settings.signature_schemes.clone()))
+        };
+        if tls_settings.pins.is_empty() {
+            return Ok(verifier);
+        }
+        Ok(Arc::new(PinningVerifier::new(verifier, tls_settings.pins.clone())))
+    }
+
+    /**
+     * Get root certificate store with the ca certificates of the settings. Without ca
+     * certificates, or if the roots are extended, the store has the webpki roots and the system
+     * root certificates. System certificates that cannot be loaded or parsed are ignored.
+     */
+    fn get_root_store(tls_settings: &TlsSettings) -> Result<RootCertStore, ClientError> {
+        let mut root_store = RootCertStore::empty();
+        if tls_settings.ca_certificates.is_empty() || tls_settings.extend_roots {
+            root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+            let native_certs = rustls_native_certs::load_native_certs();
+            root_store.add_parsable_certificates(native_certs.certs);
+        }
+        for ca_certificate in &tls_settings.ca_certificates {
+            if let Err(err) = root_store.add(ca_certificate.clone()) {
+                return Err(ClientError::new(ClientErrorType::InvalidCertificate, format!("CA certificate cannot be used as a trust anchor: {}", err)));
+            }
+        }
+        if root_store.is_empty() {
+            return Err(ClientError::new(ClientErrorType::TlsError, "No root certificates available".to_string()));
+        }
+        Ok(root_store)
+    }
+
+    /**
+     * Get the server name used for SNI and certificate verification.
+     * IP literals are verified against the IP addresses of the certificate and are not sent as
+     * SNI. The brackets of an IPv6 literal, as in the host of an url, are removed.
+     */
+    fn get_server_name(&self) -> Result<ServerName<'static>, ClientError> {
+        let server_name = self.server_name.strip_prefix('[').and_then(|server_name| server_name.strip_suffix(']')).unwrap_or(&self.server_name);
+        if let Ok(ip_addr) = server_name.parse::<IpAddr>() {
+            return Ok(ServerName::IpAddress(ip_addr.into()));
+        }
+        match ServerName::try_from(server_name.to_string()) {
+            Ok(server_name) => Ok(server_name),
+            Err(err) => Err(ClientError::new(ClientErrorType::InvalidServerName, err.to_string()))
+        }
+    }
+
+    /**
+     * Get the established tls connection and the underlying tcp stream.
+     */
+    fn get_stream(&mut self) -> Result<Stream<'_, ClientConnection, std::net::TcpStream>, ClientError> {
+        let tls_connection = match &mut self.tls_connection {
+            None => return Err(ClientError::new(ClientErrorType::NoAvailableTcpStream, "Could not retrieve tls connection".to_string())),
+            Some(tls_connection) => tls_connection
+        };
+        let tcp_stream = self.tcp_connection.get_tcp_stream()?;
+        Ok(Stream::new(tls_connection, tcp_stream))
+    }
+}
+
+impl Connection for TlsConnection {
+    /**
+     * Connects to the host and port and performs the tls handshake.
+     * The result of the handshake is kept as the tls info of the connection.
+     * Returns an error if the connection, the handshake or the certificate verification fails.
+     */
+    fn connect(&mut self) -> Result<(), ClientError> {
+        let server_name = self.get_server_name()?;
+        self.tcp_connection.connect()?;
+        let mut tls_connection = match ClientConnection::new(self.client_config.clone(), server_name.clone()) {
+            Ok(tls_connection) => tls_connection,
+            Err(err) => return Err(ClientError::new(ClientErrorType::TlsError, err.to_string()))
+        };
+        let tcp_stream = self.tcp_connection.get_tcp_stream()?;
+        while tls_connection.is_handshaking() {
+            if let Err(err) = tls_connection.complete_io(tcp_stream) {
+                return Err(get_pin_mismatch_error(&err).unwrap_or(ClientError::new(ClientErrorType::TlsError, err.to_string())));
+            }
+        }
+        self.tls_info = Some(TlsInfo::from_connection(&tls_connection, &server_name)?);
+        self.tls_connection = Some(tls_connection);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), ClientError> {
+        let mut stream = self.get_stream()?;
+        let write_result = stream.write_all(data).and_then(|_| stream.flush());
+        match write_result {
+            Ok(_) => Ok(()),
+            Err(_) => Err(ClientError::new(ClientErrorType::WriteError, "Could not write data".to_string()))
+        }
+    }
+
+    fn is_not_connected(&self) -> bool {
+        self.tls_connection.is_none() || self.tcp_connection.is_not_connected()
+    }
+
+    /**
+     * Returns true if the server has closed the connection or has sent application data
+     * without a request. Tls messages without application data, like session tickets, are
+     * processed without making the connection stale.
+     */
+    fn is_stale(&mut self) -> bool {
+        loop {
+            if self.tls_connection.is_none() || !self.tcp_connection.is_stale() {
+                return self.tls_connection.is_none();
+            }
+            let tls_connection = match &mut self.tls_connection {
+                None => return true,
+                Some(tls_connection) => tls_connection
+            };
+            let tcp_stream = match self.tcp_connection.get_tcp_stream() {
+                Ok(tcp_stream) => tcp_stream,
+                Err(_) => return true
+            };
+            match tls_connection.read_tls(tcp_stream) {
+                Ok(0) | Err(_) => return true,
+                Ok(_) => {}
+            }
+            match tls_connection.process_new_packets() {
+                Ok(io_state) if io_state.plaintext_bytes_to_read() == 0 && !io_state.peer_has_closed() => {},
+                _ => return true
+            }
+        }
+    }
+
+    /**
+     * Sends close_notify to the server and closes the tcp connection.
+     */
+    fn close(&mut self) {
+        if let Some(mut tls_connection) = self.tls_connection.take() {
+            tls_connection.send_close_notify();
+            if let Ok(tcp_stream) = self.tcp_connection.get_tcp_stream() {
+                let _ = tls_connection.write_tls(tcp_stream);
+            }
+        }
+        self.tcp_connection.close();
+        self.tls_info = None;
+    }
+
+    fn get_tls_info(&self) -> Option<TlsInfo> {
+        self.tls_info.clone()
+    }
+}
+
+/**
+ * Reads decrypted data from the tls connection.
+ * A server closing the tcp connection without sending close_notify is reported as an
+ * UnexpectedEof error, as the data may have been truncated. The response reader decides if
+ * the end of the stream is acceptable, as it is after a body framed by its length.
+ */
+impl Read for TlsConnection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut stream = match self.get_stream() {
+            Ok(stream) => stream,
+            Err(err) => return Err(std::io::Error::new(ErrorKind::NotConnected, err.message))
+        };
+        stream.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_get_client_config() {
+        assert!(TlsConnection::get_client_config().is_ok());
+    }
+
+    #[test]
+    fn test_get_server_name_invalid() {
+        let tcp_connection = TcpConnection::new("localhost".to_string(), 443, Duration::from_secs(5));
+        let tls_connection = TlsConnection::new(tcp_connection, "not a host name".to_string(), TlsConnection::get_client_config().unwrap());
+        let error = tls_connection.get_server_name().err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::InvalidServerName);
+    }
+
+    #[test]
+    fn test_get_server_name_ip_address() {
+        for (server_name, ip_addr) in [("127.0.0.1", "127.0.0.1"), ("[::1]", "::1"), ("::1", "::1")] {
+            let tcp_connection = TcpConnection::new("localhost".to_string(), 443, Duration::from_secs(5));
+            let tls_connection = TlsConnection::new(tcp_connection, server_name.to_string(), TlsConnection::get_client_config().unwrap());
+            let expected = ServerName::IpAddress(ip_addr.parse::<IpAddr>().unwrap().into());
+            assert_eq!(tls_connection.get_server_name().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_write_not_connected() {
+        let tcp_connection = TcpConnection::new("localhost".to_string(), 443, Duration::from_secs(5));
+        let mut tls_connection = TlsConnection::new(tcp_connection, "localhost".to_string(), TlsConnection::get_client_config().unwrap());
+        assert!(tls_connection.is_not_connected());
+        let error = Connection::write(&mut tls_connection, b"GET / HTTP/1.1\r\n\r\n").err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::NoAvailableTcpStream);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "network"), ignore)]
+    fn test_connect_and_read() {
+        let tcp_connection = TcpConnection::new("example.com".to_string(), 443, Duration::from_secs(5));
+        let mut tls_connection = TlsConnection::new(tcp_connection, "example.com".to_string(), TlsConnection::get_client_config().unwrap());
+        tls_connection.connect().unwrap();
+        assert!(!tls_connection.is_not_connected());
+        tls_connection.write(b"GET / HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        tls_connection.read_to_end(&mut response).unwrap();
+        assert!(response.starts_with(b"HTTP/1.1"));
+    }
+}
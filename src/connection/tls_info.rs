@@ -0,0 +1,287 @@
+use std::fmt;
+use std::net::IpAddr;
+
+use aws_lc_rs::digest;
+use rustls::pki_types::ServerName;
+use rustls::{ ClientConnection, HandshakeKind };
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use x509_parser::objects::{ oid2sn, oid_registry };
+use x509_parser::oid_registry::Oid;
+use x509_parser::prelude::FromDer;
+use x509_parser::public_key::PublicKey;
+use x509_parser::time::ASN1Time;
+
+use crate::common::{ ClientError, ClientErrorType };
+use crate::connection::PublicKeyPin;
+
+/**
+ * Result of a tls handshake: the negotiated parameters and the certificate chain sent by
+ * the server, with the server certificate first.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsInfo {
+    // Example TLSv1.3
+    pub protocol_version: String,
+    // Example TLS13_AES_256_GCM_SHA384
+    pub cipher_suite: String,
+    // Example X25519. None if no key exchange was done, for a resumed TLS 1.2 session.
+    pub key_exchange_group: Option<String>,
+    // Protocol selected by the server with ALPN. Example http/1.1
+    pub alpn_protocol: Option<String>,
+    // Server name sent with SNI. None if the server is an ip address, as no SNI is sent.
+    pub server_name: Option<String>,
+    pub handshake_kind: String,
+    pub resumed: bool,
+    pub peer_certificates: Vec<CertificateInfo>
+}
+
+impl TlsInfo {
+    /**
+     * Get the tls info of a connection after the handshake with the server name it was made for.
+     * Returns an error if the handshake is not complete or a certificate cannot be parsed.
+     */
+    pub fn from_connection(tls_connection: &ClientConnection, server_name: &ServerName) -> Result<TlsInfo, ClientError> {
+        let (protocol_version, cipher_suite, handshake_kind) = match (tls_connection.protocol_version(), tls_connection.negotiated_cipher_suite(), tls_connection.handshake_kind()) {
+            (Some(protocol_version), Some(cipher_suite), Some(handshake_kind)) => (protocol_version, cipher_suite, handshake_kind),
+            _ => return Err(ClientError::new(ClientErrorType::TlsError, "Tls handshake is not complete".to_string()))
+        };
+        let mut peer_certificates: Vec<CertificateInfo> = Vec::new();
+        for certificate in tls_connection.peer_certificates().unwrap_or_default() {
+            peer_certificates.push(CertificateInfo::parse(certificate)?);
+        }
+        Ok(TlsInfo {
+            protocol_version: format!("{:?}", protocol_version).replace('_', "."),
+            cipher_suite: format!("{:?}", cipher_suite.suite()),
+            key_exchange_group: tls_connection.negotiated_key_exchange_group().map(|group| format!("{:?}", group.name())),
+            alpn_protocol: tls_connection.alpn_protocol().map(|protocol| String::from_utf8_lossy(protocol).to_string()),
+            server_name: match server_name {
+                ServerName::DnsName(dns_name) => Some(dns_name.as_ref().to_string()),
+                _ => None
+            },
+            handshake_kind: format!("{:?}", handshake_kind),
+            resumed: handshake_kind == HandshakeKind::Resumed,
+            peer_certificates
+        })
+    }
+}
+
+/**
+ * Displays the tls info as a report, one field per line, followed by the certificates.
+ */
+impl fmt::Display for TlsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Protocol version : {}", self.protocol_version)?;
+        writeln!(f, "Cipher suite : {}", self.cipher_suite)?;
+        writeln!(f, "Key exchange group : {}", self.key_exchange_group.as_deref().unwrap_or("none"))?;
+        writeln!(f, "ALPN protocol : {}", self.alpn_protocol.as_deref().unwrap_or("none"))?;
+        writeln!(f, "Server name (SNI) : {}", self.server_name.as_deref().unwrap_or("none"))?;
+        write!(f, "Session resumed : {} ({})", if self.resumed { "yes" } else { "no" }, self.handshake_kind)?;
+        for (index, certificate) in self.peer_certificates.iter().enumerate() {
+            write!(f, "\nCertificate {}\n{}", index, certificate)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Details of a X.509 certificate, as described in RFC 5280.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertificateInfo {
+    // Example CN=localhost
+    pub subject: String,
+    pub issuer: String,
+    // Serial number as colon separated hex. Example 12:34
+    pub serial_number: String,
+    // Subject alternative names. Example DNS:localhost, IP:127.0.0.1
+    pub subject_alt_names: Vec<String>,
+    // Validity in seconds since the unix epoch.
+    pub not_before: i64,
+    pub not_after: i64,
+    // Example EC prime256v1 256 bits
+    pub key_type: String,
+    // Example sha256WithRSAEncryption
+    pub signature_algorithm: String,
+    // Fingerprints of the DER encoding as colon separated hex.
+    pub sha256_fingerprint: String,
+    pub sha1_fingerprint: String,
+    // Pin of the public key for --pin. Example sha256//+bQaTac5RjG58iV1x2aWZoxV+1ZBG4u6IRqFWP3KAdY=
+    pub public_key_pin: String
+}
+
+impl CertificateInfo {
+    /**
+     * Parses a DER encoded certificate.
+     */
+    pub fn parse(der: &[u8]) -> Result<CertificateInfo, ClientError> {
+        let certificate = match X509Certificate::from_der(der) {
+            Ok((_, certificate)) => certificate,
+            Err(err) => return Err(ClientError::new(ClientErrorType::TlsError, format!("Certificate could not be parsed: {}", err)))
+        };
+        let subject_alt_names = match certificate.subject_alternative_name() {
+            Ok(Some(extension)) => extension.value.general_names.iter().map(get_general_name).collect(),
+            Ok(None) => Vec::new(),
+            Err(err) => return Err(ClientError::new(ClientErrorType::TlsError, format!("Certificate subject alternative names could not be parsed: {}", err)))
+        };
+        Ok(CertificateInfo {
+            subject: certificate.subject().to_string(),
+            issuer: certificate.issuer().to_string(),
+            serial_number: certificate.raw_serial_as_string(),
+            subject_alt_names,
+            not_before: certificate.validity().not_before.timestamp(),
+            not_after: certificate.validity().not_after.timestamp(),
+            key_type: get_key_type(&certificate),
+            signature_algorithm: get_oid_name(&certificate.signature_algorithm.algorithm),
+            sha256_fingerprint: get_fingerprint(&digest::SHA256, der),
+            sha1_fingerprint: get_fingerprint(&digest::SHA1_FOR_LEGACY_USE_ONLY, der),
+            public_key_pin: PublicKeyPin::from_certificate(der)?.to_string()
+        })
+    }
+}
+
+impl fmt::Display for CertificateInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  Subject : {}", self.subject)?;
+        writeln!(f, "  Issuer : {}", self.issuer)?;
+        writeln!(f, "  Serial number : {}", self.serial_number)?;
+        writeln!(f, "  Subject alternative names : {}", self.subject_alt_names.join(", "))?;
+        writeln!(f, "  Not before : {}", get_time(self.not_before))?;
+        writeln!(f, "  Not after : {}", get_time(self.not_after))?;
+        writeln!(f, "  Key type : {}", self.key_type)?;
+        writeln!(f, "  Signature algorithm : {}", self.signature_algorithm)?;
+        writeln!(f, "  SHA-256 fingerprint : {}", self.sha256_fingerprint)?;
+        writeln!(f, "  SHA-1 fingerprint : {}", self.sha1_fingerprint)?;
+        write!(f, "  Public key pin : {}", self.public_key_pin)
+    }
+}
+
+/**
+ * Get the short name of an oid, or the dotted oid if it is not known.
+ * Example: sha256WithRSAEncryption
+ */
+fn get_oid_name(oid: &Oid) -> String {
+    match oid2sn(oid, oid_registry()) {
+        Ok(name) => name.to_string(),
+        Err(_) => oid.to_id_string()
+    }
+}
+
+/**
+ * Get the type of the public key, with the curve and the size in bits.
+ * Example: RSA 2048 bits, EC prime256v1 256 bits
+ */
+fn get_key_type(certificate: &X509Certificate) -> String {
+    let public_key = certificate.public_key();
+    match public_key.parsed() {
+        Ok(PublicKey::RSA(rsa)) => format!("RSA {} bits", rsa.key_size()),
+        Ok(PublicKey::EC(ec)) => {
+            let curve = public_key.algorithm.parameters.as_ref().and_then(|parameters| parameters.as_oid().ok());
+            match curve {
+                Some(curve) => format!("EC {} {} bits", get_oid_name(&curve), ec.key_size()),
+                None => format!("EC {} bits", ec.key_size())
+            }
+        },
+        _ => get_oid_name(&public_key.algorithm.algorithm)
+    }
+}
+
+fn get_general_name(general_name: &GeneralName) -> String {
+    match general_name {
+        GeneralName::DNSName(name) => format!("DNS:{}", name),
+        GeneralName::IPAddress(address) => match <[u8; 4]>::try_from(*address) {
+            Ok(octets) => format!("IP:{}", IpAddr::from(octets)),
+            Err(_) => match <[u8; 16]>::try_from(*address) {
+                Ok(octets) => format!("IP:{}", IpAddr::from(octets)),
+                Err(_) => format!("IP:{}", to_hex(address))
+            }
+        },
+        GeneralName::RFC822Name(email) => format!("email:{}", email),
+        GeneralName::URI(uri) => format!("URI:{}", uri),
+        GeneralName::DirectoryName(name) => format!("DirName:{}", name),
+        general_name => format!("{:?}", general_name)
+    }
+}
+
+fn get_fingerprint(algorithm: &'static digest::Algorithm, der: &[u8]) -> String {
+    to_hex(digest::digest(algorithm, der).as_ref())
+}
+
+/**
+ * Get the bytes as upper case hex separated by colons. Example: 5B:B1:F6
+ */
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(":")
+}
+
+/**
+ * Get the time in seconds since the unix epoch as text. Example: Jan  1 00:00:00 2025 +00:00
+ */
+fn get_time(timestamp: i64) -> String {
+    match ASN1Time::from_timestamp(timestamp) {
+        Ok(time) => time.to_string(),
+        Err(_) => timestamp.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use crate::connection::test_tls::{ get_certificate, get_client_config, get_server_config, start_server, CA_CERTIFICATE, SERVER_CERTIFICATE, SERVER_CERTIFICATE_SHA256, SERVER_PUBLIC_KEY_PIN };
+    use crate::connection::{ TlsConnection, TlsSettings };
+    use crate::http::{ HeaderMap, HttpClient, HttpRequest };
+
+    const RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+
+    #[test]
+    fn test_parse_certificate() {
+        let certificate_info = CertificateInfo::parse(&get_certificate(SERVER_CERTIFICATE)).unwrap();
+        assert_eq!(certificate_info.subject, "CN=localhost");
+        assert_eq!(certificate_info.issuer, "CN=http-client test ca, O=http-client");
+        assert_eq!(certificate_info.serial_number, "12:34");
+        assert_eq!(certificate_info.subject_alt_names, vec!["DNS:localhost", "IP:127.0.0.1"]);
+        assert_eq!(certificate_info.not_before, 1735689600);
+        assert_eq!(certificate_info.not_after, 2366841600);
+        assert_eq!(certificate_info.key_type, "EC prime256v1 256 bits");
+        assert_eq!(certificate_info.signature_algorithm, "sha256WithRSAEncryption");
+        assert_eq!(certificate_info.sha256_fingerprint, SERVER_CERTIFICATE_SHA256);
+        assert_eq!(certificate_info.sha1_fingerprint, "86:52:2D:A3:86:9E:6B:FE:7B:56:91:3C:80:9C:B0:E9:E2:EA:AC:39");
+        assert_eq!(certificate_info.public_key_pin, SERVER_PUBLIC_KEY_PIN);
+        assert!(certificate_info.to_string().contains("  Not before : Jan  1 00:00:00 2025 +00:00\n"));
+        let ca_info = CertificateInfo::parse(&get_certificate(CA_CERTIFICATE)).unwrap();
+        assert_eq!(ca_info.key_type, "RSA 2048 bits");
+        assert!(ca_info.subject_alt_names.is_empty());
+        assert_eq!(CertificateInfo::parse(b"not a certificate").err().unwrap().error_type, ClientErrorType::TlsError);
+    }
+
+    #[test]
+    fn test_tls_info_of_response() {
+        let port = start_server(get_server_config(), RESPONSE);
+        let client_config = get_client_config();
+        let mut tls_infos: Vec<TlsInfo> = Vec::new();
+        for _ in 0..2 {
+            let mut http_client = HttpClient::new_tls("127.0.0.1".to_string(), port, Duration::from_secs(5), "localhost".to_string(), client_config.clone());
+            let http_request = HttpRequest::new("/".to_string(), "GET".to_string(), "localhost".to_string(), HeaderMap::new(), None);
+            tls_infos.push(http_client.send(http_request).unwrap().tls_info.unwrap());
+        }
+        let tls_info = &tls_infos[0];
+        assert_eq!(tls_info.protocol_version, "TLSv1.3");
+        assert!(tls_info.cipher_suite.starts_with("TLS13_"));
+        assert!(tls_info.key_exchange_group.is_some());
+        assert_eq!(tls_info.alpn_protocol.as_deref(), Some("http/1.1"));
+        assert_eq!(tls_info.server_name.as_deref(), Some("localhost"));
+        assert_eq!((tls_info.resumed, tls_info.handshake_kind.as_str()), (false, "Full"));
+        assert_eq!(tls_info.peer_certificates.len(), 2);
+        assert_eq!(tls_info.peer_certificates[1].subject, "CN=http-client test ca, O=http-client");
+        assert!(tls_info.to_string().starts_with("Protocol version : TLSv1.3\nCipher suite : TLS13_"));
+        assert!(tls_infos[1].resumed);
+        // No SNI is sent for an IPv6 address, the certificate does not name it so it is not verified.
+        let client_config = TlsConnection::get_client_config_with(&TlsSettings::default().with_insecure(true)).unwrap();
+        let mut http_client = HttpClient::new_tls("127.0.0.1".to_string(), port, Duration::from_secs(5), "[::1]".to_string(), client_config);
+        let http_request = HttpRequest::new("/".to_string(), "GET".to_string(), "[::1]".to_string(), HeaderMap::new(), None);
+        let tls_info = http_client.send(http_request).unwrap().tls_info.unwrap();
+        assert_eq!(tls_info.server_name, None);
+        assert!(tls_info.to_string().contains("\nServer name (SNI) : none\n"));
+    }
+}
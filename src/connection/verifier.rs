@@ -0,0 +1,263 @@
+use std::fmt;
+use std::sync::Arc;
+
+use aws_lc_rs::digest;
+use rustls::client::danger::{ HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier };
+use rustls::crypto::{ verify_tls12_signature, verify_tls13_signature, CryptoProvider };
+use rustls::pki_types::{ CertificateDer, ServerName, UnixTime };
+use rustls::{ CertificateError, DigitallySignedStruct, OtherError, PeerMisbehaved, SignatureScheme };
+use x509_parser::certificate::X509Certificate;
+use x509_parser::prelude::FromDer;
+
+use crate::common::base64;
+use crate::common::{ ClientError, ClientErrorType };
+
+const SHA256_PIN_PREFIX: &str = "sha256//";
+
+/**
+ * SHA-256 hash of the DER encoded SubjectPublicKeyInfo of a certificate, as used by curl's
+ * --pinnedpubkey and the pin-sha256 directive of RFC 7469.
+ * Example: sha256//+bQaTac5RjG58iV1x2aWZoxV+1ZBG4u6IRqFWP3KAdY=
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublicKeyPin {
+    hash: Vec<u8>
+}
+
+impl PublicKeyPin {
+    /**
+     * Parses a pin of the form sha256//<base64 hash>.
+     */
+    pub fn parse(pin: &str) -> Result<PublicKeyPin, ClientError> {
+        let hash = match pin.trim().strip_prefix(SHA256_PIN_PREFIX).map(|hash| base64::STANDARD.decode(hash)) {
+            Some(Ok(hash)) => hash,
+            _ => return Err(ClientError::new(ClientErrorType::InvalidRequest, format!("Pin {:?} is not of the form sha256//<base64 hash>", pin)))
+        };
+        if hash.len() != digest::SHA256_OUTPUT_LEN {
+            return Err(ClientError::new(ClientErrorType::InvalidRequest, format!("Pin {:?} is not a SHA-256 hash", pin)));
+        }
+        Ok(PublicKeyPin { hash })
+    }
+
+    /**
+     * Get the pin of the public key of a DER encoded certificate.
+     */
+    pub fn from_certificate(der: &[u8]) -> Result<PublicKeyPin, ClientError> {
+        match X509Certificate::from_der(der) {
+            Ok((_, certificate)) => Ok(PublicKeyPin { hash: digest::digest(&digest::SHA256, certificate.public_key().raw).as_ref().to_vec() }),
+            Err(err) => Err(ClientError::new(ClientErrorType::TlsError, format!("Certificate could not be parsed: {}", err)))
+        }
+    }
+}
+
+impl fmt::Display for PublicKeyPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SHA256_PIN_PREFIX, base64::STANDARD.encode(&self.hash))
+    }
+}
+
+/**
+ * Error of the handshake when the public key of the server certificate matches none of the pins.
+ */
+#[derive(Debug)]
+struct PinMismatch {
+    pin: PublicKeyPin
+}
+
+impl fmt::Display for PinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Public key {} of the server certificate does not match a pin", self.pin)
+    }
+}
+
+impl std::error::Error for PinMismatch {}
+
+/**
+ * Get the PinMismatch error if the handshake failed because the server certificate matches none
+ * of the pins, or None for other errors.
+ */
+pub(crate) fn get_pin_mismatch_error(err: &std::io::Error) -> Option<ClientError> {
+    match err.get_ref().and_then(|err| err.downcast_ref::<rustls::Error>()) {
+        Some(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(err)))) => {
+            err.downcast_ref::<PinMismatch>().map(|pin_mismatch| ClientError::new(ClientErrorType::PinMismatch, pin_mismatch.to_string()))
+        },
+        _ => None
+    }
+}
+
+/**
+ * Verifier accepting any server certificate. The signatures of the handshake are still
+ * verified, so the server must have the key of the certificate it sends.
+ */
+#[derive(Debug)]
+pub(crate) struct InsecureVerifier {
+    provider: Arc<CryptoProvider>
+}
+
+impl InsecureVerifier {
+    pub(crate) fn new(provider: Arc<CryptoProvider>) -> InsecureVerifier {
+        InsecureVerifier { provider }
+    }
+}
+
+impl ServerCertVerifier for InsecureVerifier {
+    fn verify_server_cert(&self, _end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], _server_name: &ServerName<'_>, _ocsp_response: &[u8], _now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/**
+ * Verifier checking the public key of the server certificate against the pins after the
+ * inner verifier accepted the certificate.
+ */
+#[derive(Debug)]
+pub(crate) struct PinningVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    pins: Vec<PublicKeyPin>
+}
+
+impl PinningVerifier {
+    pub(crate) fn new(inner: Arc<dyn ServerCertVerifier>, pins: Vec<PublicKeyPin>) -> PinningVerifier {
+        PinningVerifier { inner, pins }
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>], server_name: &ServerName<'_>, ocsp_response: &[u8], now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        let pin = match PublicKeyPin::from_certificate(end_entity) {
+            Ok(pin) => pin,
+            Err(_) => return Err(rustls::Error::InvalidCertificate(CertificateError::BadEncoding))
+        };
+        if !self.pins.contains(&pin) {
+            return Err(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(Arc::new(PinMismatch { pin })))));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/**
+ * Verifier offering only the signature schemes, in their order, of those the inner verifier
+ * supports. Handshake signatures with other schemes are rejected.
+ */
+#[derive(Debug)]
+pub(crate) struct SignatureSchemeVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    signature_schemes: Vec<SignatureScheme>
+}
+
+impl SignatureSchemeVerifier {
+    pub(crate) fn new(inner: Arc<dyn ServerCertVerifier>, signature_schemes: Vec<SignatureScheme>) -> SignatureSchemeVerifier {
+        let supported = inner.supported_verify_schemes();
+        let signature_schemes = signature_schemes.into_iter().filter(|signature_scheme| supported.contains(signature_scheme)).collect();
+        SignatureSchemeVerifier { inner, signature_schemes }
+    }
+
+    fn check_scheme(&self, dss: &DigitallySignedStruct) -> Result<(), rustls::Error> {
+        if !self.signature_schemes.contains(&dss.scheme) {
+            return Err(rustls::Error::PeerMisbehaved(PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme));
+        }
+        Ok(())
+    }
+}
+
+impl ServerCertVerifier for SignatureSchemeVerifier {
+    fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>], server_name: &ServerName<'_>, ocsp_response: &[u8], now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.check_scheme(dss)?;
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.check_scheme(dss)?;
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.signature_schemes.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use crate::connection::test_tls::{ get_certificate, get_server_config, start_server, CA_CERTIFICATE, CLIENT_CERTIFICATE, SERVER_CERTIFICATE, SERVER_PUBLIC_KEY_PIN };
+    use crate::connection::{ TlsConnection, TlsSettings };
+    use crate::http::{ HeaderMap, HttpClient, HttpRequest };
+
+    const RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+
+    fn send(port: u16, tls_settings: TlsSettings) -> Result<u16, ClientError> {
+        let client_config = TlsConnection::get_client_config_with(&tls_settings)?;
+        let mut http_client = HttpClient::new_tls("127.0.0.1".to_string(), port, Duration::from_secs(5), "localhost".to_string(), client_config);
+        let http_request = HttpRequest::new("/".to_string(), "GET".to_string(), "localhost".to_string(), HeaderMap::new(), None);
+        http_client.send(http_request).map(|http_response| http_response.response_code)
+    }
+
+    #[test]
+    fn test_parse_pin() {
+        let pin = PublicKeyPin::parse(SERVER_PUBLIC_KEY_PIN).unwrap();
+        assert_eq!(pin.to_string(), SERVER_PUBLIC_KEY_PIN);
+        assert_eq!(PublicKeyPin::from_certificate(&get_certificate(SERVER_CERTIFICATE)).unwrap(), pin);
+        assert_ne!(PublicKeyPin::from_certificate(&get_certificate(CLIENT_CERTIFICATE)).unwrap(), pin);
+        assert_eq!(PublicKeyPin::parse("sha1//+bQaTac5RjG58iV1x2aWZoxV+1ZBG4u6IRqFWP3KAdY=").err().unwrap().error_type, ClientErrorType::InvalidRequest);
+        assert_eq!(PublicKeyPin::parse("sha256//AAAA").err().unwrap().error_type, ClientErrorType::InvalidRequest);
+        assert_eq!(PublicKeyPin::parse("sha256//not base64").err().unwrap().error_type, ClientErrorType::InvalidRequest);
+    }
+
+    #[test]
+    fn test_verify_server_certificate() {
+        let port = start_server(get_server_config(), RESPONSE);
+        let ca_certificates = vec![get_certificate(CA_CERTIFICATE)];
+        assert_eq!(send(port, TlsSettings::default()).err().unwrap().error_type, ClientErrorType::TlsError);
+        assert_eq!(send(port, TlsSettings::default().with_ca_certificates(ca_certificates.clone(), false)).unwrap(), 200);
+        assert_eq!(send(port, TlsSettings::default().with_ca_certificates(ca_certificates.clone(), true)).unwrap(), 200);
+        assert_eq!(send(port, TlsSettings::default().with_insecure(true)).unwrap(), 200);
+    }
+
+    #[test]
+    fn test_verify_pins() {
+        let port = start_server(get_server_config(), RESPONSE);
+        let ca_certificates = vec![get_certificate(CA_CERTIFICATE)];
+        let pin = PublicKeyPin::parse(SERVER_PUBLIC_KEY_PIN).unwrap();
+        let other_pin = PublicKeyPin::from_certificate(&get_certificate(CA_CERTIFICATE)).unwrap();
+        let tls_settings = TlsSettings::default().with_ca_certificates(ca_certificates.clone(), false).with_pins(vec![other_pin.clone(), pin]);
+        assert_eq!(send(port, tls_settings).unwrap(), 200);
+        let tls_settings = TlsSettings::default().with_ca_certificates(ca_certificates, false).with_pins(vec![other_pin.clone()]);
+        let error = send(port, tls_settings).err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::PinMismatch);
+        assert!(error.message.contains(SERVER_PUBLIC_KEY_PIN));
+        let tls_settings = TlsSettings::default().with_insecure(true).with_pins(vec![other_pin]);
+        assert_eq!(send(port, tls_settings).err().unwrap().error_type, ClientErrorType::PinMismatch);
+        let tls_settings = TlsSettings::default().with_pins(vec![PublicKeyPin::parse(SERVER_PUBLIC_KEY_PIN).unwrap()]);
+        assert_eq!(send(port, tls_settings).err().unwrap().error_type, ClientErrorType::TlsError);
+    }
+}
@@ -0,0 +1,199 @@
+use clap::ValueEnum;
+use sha2::{ Digest, Sha256, Sha512 };
+
+use crate::common::{ ClientError, ClientErrorType };
+use crate::http::{ HttpRequest, HttpResponse, RequestBody };
+use crate::http::structured_field::{ parse_dictionary, Item, Member };
+
+pub const CONTENT_DIGEST: &str = "Content-Digest";
+pub const REPR_DIGEST: &str = "Repr-Digest";
+const WANT_CONTENT_DIGEST: &str = "Want-Content-Digest";
+const WANT_REPR_DIGEST: &str = "Want-Repr-Digest";
+const WANT_DIGEST_PREFERENCES: &str = "sha-512=10, sha-256=9";
+const HEAD_METHOD: &str = "HEAD";
+const NOT_MODIFIED: u16 = 304;
+const PARTIAL_CONTENT: u16 = 206;
+
+/**
+ * Hash algorithm of a digest field, as registered in RFC 9530 section 5.
+ * Insecure algorithms, such as md5 and sha, are not supported and are ignored when verifying.
+ */
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum HashAlgorithm {
+    #[value(name = "sha-256")]
+    Sha256,
+    #[value(name = "sha-512")]
+    Sha512,
+}
+
+impl HashAlgorithm {
+    pub fn parse(name: &str) -> Option<HashAlgorithm> {
+        match name {
+            "sha-256" => Some(HashAlgorithm::Sha256),
+            "sha-512" => Some(HashAlgorithm::Sha512),
+            _ => None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha-256",
+            HashAlgorithm::Sha512 => "sha-512"
+        }
+    }
+
+    pub fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            HashAlgorithm::Sha512 => Sha512::digest(data).to_vec()
+        }
+    }
+}
+
+/**
+ * Get the value of a digest field of the data.
+ * Example: sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:
+ */
+pub fn get_digest_value(algorithm: HashAlgorithm, data: &[u8]) -> String {
+    format!("{}={}", algorithm.get_name(), Item::ByteSequence(algorithm.hash(data)).serialize())
+}
+
+/**
+ * Adds the Content-Digest of the body to the request, as described in RFC 9530 section 2.
+ * A request without a body gets the digest of empty content. Returns an error if the body is
+ * streamed, as the digest must be sent before the body.
+ */
+pub fn add_content_digest(http_request: &mut HttpRequest, algorithm: HashAlgorithm) -> Result<(), ClientError> {
+    let value = match &http_request.body {
+        Some(RequestBody::Bytes(body)) => get_digest_value(algorithm, body),
+        Some(RequestBody::Stream(_)) => return Err(ClientError::new(ClientErrorType::InvalidRequest, "Content-Digest cannot be computed for a streamed body".to_string())),
+        None => get_digest_value(algorithm, &[])
+    };
+    http_request.headers.insert(CONTENT_DIGEST, &value);
+    Ok(())
+}
+
+/**
+ * Asks the server to send the Content-Digest and Repr-Digest of the response, as described in
+ * RFC 9530 section 4. Preferences already in the request are kept.
+ */
+pub fn add_want_digest(http_request: &mut HttpRequest) {
+    for name in [WANT_CONTENT_DIGEST, WANT_REPR_DIGEST] {
+        if !http_request.headers.contains(name) {
+            http_request.headers.append(name, WANT_DIGEST_PREFERENCES);
+        }
+    }
+}
+
+/**
+ * Digest verified by verify_response_digests, the field and the algorithm.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedDigest {
+    pub field: &'static str,
+    pub algorithm: HashAlgorithm
+}
+
+/**
+ * Verifies the Content-Digest and Repr-Digest of a response to a request with the method.
+ * The digests are computed over the body after the transfer coding is removed. Content codings
+ * are not removed, so the body is both the content and the representation data. Fields are
+ * read from the headers or, if not there, from the trailers. Every supported algorithm of a
+ * field is verified, other algorithms are ignored.
+ * Responses to HEAD and 304 responses have no content, and the Repr-Digest of a 206 response is
+ * the digest of the complete representation, so these are not verified.
+ * Returns the verified digests, a DigestMismatch error if a digest does not match, or a
+ * MissingDigest error if the response has no digest that can be verified.
+ */
+pub fn verify_response_digests(http_response: &HttpResponse, method: &str) -> Result<Vec<VerifiedDigest>, ClientError> {
+    if method.eq_ignore_ascii_case(HEAD_METHOD) || http_response.response_code == NOT_MODIFIED {
+        return Ok(Vec::new());
+    }
+    let mut verified: Vec<VerifiedDigest> = Vec::new();
+    verified.extend(verify_digest_field(CONTENT_DIGEST, http_response)?);
+    if http_response.response_code != PARTIAL_CONTENT {
+        verified.extend(verify_digest_field(REPR_DIGEST, http_response)?);
+    }
+    if verified.is_empty() {
+        return Err(ClientError::new(ClientErrorType::MissingDigest, "Response has no Content-Digest or Repr-Digest with a supported algorithm".to_string()));
+    }
+    Ok(verified)
+}
+
+fn verify_digest_field(field: &'static str, http_response: &HttpResponse) -> Result<Vec<VerifiedDigest>, ClientError> {
+    let mut verified: Vec<VerifiedDigest> = Vec::new();
+    let value = match http_response.headers.get_combined(field).or_else(|| http_response.trailers.get_combined(field)) {
+        Some(value) => value,
+        None => return Ok(verified)
+    };
+    for (name, member) in parse_dictionary(&value)? {
+        let algorithm = match HashAlgorithm::parse(&name) {
+            Some(algorithm) => algorithm,
+            None => continue
+        };
+        match member {
+            Member::Item(Item::ByteSequence(digest), _) if digest == algorithm.hash(&http_response.body) => {
+                verified.push(VerifiedDigest { field, algorithm });
+            },
+            Member::Item(Item::ByteSequence(_), _) => {
+                return Err(ClientError::new(ClientErrorType::DigestMismatch, format!("{} {} does not match the response", field, name)));
+            },
+            _ => return Err(ClientError::new(ClientErrorType::InvalidHeader, format!("{} {} is not a byte sequence", field, name)))
+        }
+    }
+    Ok(verified)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::BufReader;
+    use crate::http::HeaderMap;
+
+    fn get_response(response: &str) -> HttpResponse {
+        HttpResponse::read(&mut BufReader::new(response.as_bytes()), "GET").unwrap()
+    }
+
+    #[test]
+    fn test_get_digest_value() {
+        // Examples of RFC 9530 appendix B.
+        assert_eq!(get_digest_value(HashAlgorithm::Sha256, b"{\"hello\": \"world\"}"), "sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:");
+        assert_eq!(get_digest_value(HashAlgorithm::Sha512, b"{\"hello\": \"world\"}"), "sha-512=:WZDPaVn/7XgHaAy8pmojAkGWoRx2UFChF41A2svX+TaPm+AbwAgBWnrIiYllu7BNNyealdVLvRwEmTHWXvJwew==:");
+    }
+
+    #[test]
+    fn test_add_content_digest() {
+        let mut http_request = HttpRequest::new("/".to_string(), "POST".to_string(), "example.com".to_string(), HeaderMap::new(), Some(RequestBody::from_string("{\"hello\": \"world\"}".to_string())));
+        add_content_digest(&mut http_request, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(http_request.headers.get("content-digest"), Some("sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:"));
+        add_want_digest(&mut http_request);
+        assert_eq!(http_request.headers.get("want-repr-digest"), Some("sha-512=10, sha-256=9"));
+        http_request.body = Some(RequestBody::Stream(Box::new(std::io::empty())));
+        assert_eq!(add_content_digest(&mut http_request, HashAlgorithm::Sha256).err().unwrap().error_type, ClientErrorType::InvalidRequest);
+    }
+
+    #[test]
+    fn test_verify_response_digests() {
+        let http_response = get_response("HTTP/1.1 200 OK\r\nContent-Length: 18\r\nRepr-Digest: md5=:AAAA:, sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:\r\n\r\n{\"hello\": \"world\"}");
+        assert_eq!(verify_response_digests(&http_response, "GET").unwrap(), vec![VerifiedDigest { field: REPR_DIGEST, algorithm: HashAlgorithm::Sha256 }]);
+        let http_response = get_response("HTTP/1.1 200 OK\r\nContent-Length: 18\r\nContent-Digest: sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:\r\n\r\n{\"hello\": \"World\"}");
+        let error = verify_response_digests(&http_response, "GET").err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::DigestMismatch);
+        assert_eq!(error.message, "Content-Digest sha-256 does not match the response");
+        assert!(verify_response_digests(&http_response, "HEAD").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_verify_trailer_and_missing_digests() {
+        let http_response = get_response("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n12\r\n{\"hello\": \"world\"}\r\n0\r\nContent-Digest: sha-512=:WZDPaVn/7XgHaAy8pmojAkGWoRx2UFChF41A2svX+TaPm+AbwAgBWnrIiYllu7BNNyealdVLvRwEmTHWXvJwew==:\r\n\r\n");
+        assert_eq!(verify_response_digests(&http_response, "GET").unwrap(), vec![VerifiedDigest { field: CONTENT_DIGEST, algorithm: HashAlgorithm::Sha512 }]);
+        let http_response = get_response("HTTP/1.1 200 OK\r\nContent-Length: 2\r\nContent-Digest: sha=:AAAA:\r\n\r\nok");
+        assert_eq!(verify_response_digests(&http_response, "GET").err().unwrap().error_type, ClientErrorType::MissingDigest);
+        let http_response = get_response("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        assert_eq!(verify_response_digests(&http_response, "GET").err().unwrap().error_type, ClientErrorType::MissingDigest);
+        let http_response = get_response("HTTP/1.1 206 Partial Content\r\nContent-Length: 2\r\nRepr-Digest: sha-256=:AAAA:\r\nContent-Digest: sha-256=:Jok2eyBcFs4y7UIAlCuLix4mLfxw2byfvHfElpmk8d8=:\r\n\r\nok");
+        assert_eq!(verify_response_digests(&http_response, "GET").unwrap().len(), 1);
+        let http_response = get_response("HTTP/1.1 200 OK\r\nContent-Length: 2\r\nContent-Digest: sha-256=1\r\n\r\nok");
+        assert_eq!(verify_response_digests(&http_response, "GET").err().unwrap().error_type, ClientErrorType::InvalidHeader);
+    }
+}
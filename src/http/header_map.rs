@@ -0,0 +1,271 @@
+use std::fmt;
+
+use crate::common::{ ClientError, ClientErrorType };
+
+/**
+ * Header fields of a request or response.
+ * Fields are kept in insertion order and repeated fields are kept as separate entries.
+ * Names are compared case insensitive, but keep their original casing, which is the
+ * casing sent on the wire.
+ */
+#[derive(Clone, Default, PartialEq)]
+pub struct HeaderMap {
+    headers: Vec<(String, String)>
+}
+
+impl HeaderMap {
+    /**
+     * Creates an empty header map.
+     */
+    pub fn new() -> HeaderMap {
+        HeaderMap {
+            headers: Vec::new()
+        }
+    }
+
+    /**
+     * Appends a header field, keeping existing fields with the same name.
+     */
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /**
+     * Sets a header field, replacing existing fields with the same name.
+     * The new field takes the position of the first replaced field.
+     */
+    pub fn insert(&mut self, name: &str, value: &str) {
+        match self.headers.iter().position(|header| header.0.eq_ignore_ascii_case(name)) {
+            Some(index) => {
+                self.headers[index] = (name.to_string(), value.to_string());
+                let mut current = 0;
+                self.headers.retain(|header| {
+                    let keep = current <= index || !header.0.eq_ignore_ascii_case(name);
+                    current += 1;
+                    keep
+                });
+            },
+            None => self.append(name, value)
+        }
+    }
+
+    /**
+     * Removes all fields with the name. Returns the removed values.
+     */
+    pub fn remove(&mut self, name: &str) -> Vec<String> {
+        let mut removed: Vec<String> = Vec::new();
+        self.headers.retain(|header| {
+            let matches = header.0.eq_ignore_ascii_case(name);
+            if matches {
+                removed.push(header.1.clone());
+            }
+            !matches
+        });
+        removed
+    }
+
+    /**
+     * Get the value of the first field with the name.
+     */
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|header| header.0.eq_ignore_ascii_case(name))
+            .map(|header| header.1.as_str())
+    }
+
+    /**
+     * Get the values of all fields with the name, in order.
+     */
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.headers.iter()
+            .filter(|header| header.0.eq_ignore_ascii_case(name))
+            .map(|header| header.1.as_str())
+            .collect()
+    }
+
+    /**
+     * Get the values of all fields with the name combined into a comma separated list,
+     * as described in RFC 9110 section 5.3. Not suitable for Set-Cookie, which cannot be combined.
+     */
+    pub fn get_combined(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self.get_all(name).into_iter().map(str::trim).collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(", "))
+        }
+    }
+
+    /**
+     * Returns true if a field with the name is present.
+     */
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /**
+     * Returns true if the comma separated values of the fields with the name contain the token.
+     * Tokens are compared case insensitive.
+     * Example: contains_token("Connection", "close")
+     */
+    pub fn contains_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name).iter()
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    }
+
+    /**
+     * Returns the number of fields, counting repeated fields separately.
+     */
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    /**
+     * Returns true if there are no fields.
+     */
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /**
+     * Returns an iterator over the fields in order, with the original casing of the names.
+     */
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|header| (header.0.as_str(), header.1.as_str()))
+    }
+}
+
+impl fmt::Debug for HeaderMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<N: AsRef<str>, V: AsRef<str>> FromIterator<(N, V)> for HeaderMap {
+    fn from_iter<T: IntoIterator<Item = (N, V)>>(iter: T) -> HeaderMap {
+        let mut header_map = HeaderMap::new();
+        iter.into_iter().for_each(|header| header_map.append(header.0.as_ref(), header.1.as_ref()));
+        header_map
+    }
+}
+
+impl<N: AsRef<str>, V: AsRef<str>> Extend<(N, V)> for HeaderMap {
+    fn extend<T: IntoIterator<Item = (N, V)>>(&mut self, iter: T) {
+        iter.into_iter().for_each(|header| self.append(header.0.as_ref(), header.1.as_ref()));
+    }
+}
+
+/**
+ * Parses a field line into name and value as described in RFC 9112 section 5.
+ * The line is split on the first colon and optional whitespace around the value is removed.
+ * Returns an error if the line has no colon, if the name is not a token, which includes
+ * whitespace before the colon, if the line is an obsolete line folding (starts with whitespace),
+ * or if the value contains CR, LF or NUL.
+ * Example: "Location: http://localhost:8080/" gives ("Location", "http://localhost:8080/")
+ */
+pub fn parse_field_line(line: &str) -> Result<(String, String), ClientError> {
+    if line.starts_with([' ', '\t']) {
+        return Err(ClientError::new(ClientErrorType::InvalidHeader, format!("Obsolete line folding is not supported {:?}", line)));
+    }
+    let (name, value) = match line.split_once(':') {
+        Some(field) => field,
+        None => return Err(ClientError::new(ClientErrorType::InvalidHeader, format!("Missing colon in header line {:?}", line)))
+    };
+    if name.ends_with([' ', '\t']) {
+        return Err(ClientError::new(ClientErrorType::InvalidHeader, format!("Whitespace before colon in header line {:?}", line)));
+    }
+    if !is_token(name) {
+        return Err(ClientError::new(ClientErrorType::InvalidHeader, format!("Invalid header name {:?}", name)));
+    }
+    let value = value.trim_matches([' ', '\t']);
+    if value.contains(['\r', '\n', '\0']) {
+        return Err(ClientError::new(ClientErrorType::InvalidHeader, format!("Invalid value for header {}", name)));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/**
+ * Returns true if the string is a token as defined in RFC 9110 section 5.6.2.
+ */
+pub(crate) fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_append_keeps_duplicates_and_order() {
+        let mut header_map = HeaderMap::new();
+        header_map.append("Set-Cookie", "a=1");
+        header_map.append("Vary", "Accept");
+        header_map.append("set-cookie", "b=2");
+        assert_eq!(header_map.len(), 3);
+        assert_eq!(header_map.get("SET-COOKIE"), Some("a=1"));
+        assert_eq!(header_map.get_all("Set-Cookie"), vec!["a=1", "b=2"]);
+        let names: Vec<&str> = header_map.iter().map(|header| header.0).collect();
+        assert_eq!(names, vec!["Set-Cookie", "Vary", "set-cookie"]);
+    }
+
+    #[test]
+    fn test_insert_replaces() {
+        let mut header_map: HeaderMap = [("Accept", "text/html"), ("Host", "localhost"), ("accept", "text/xml")].into_iter().collect();
+        header_map.insert("ACCEPT", "application/json");
+        assert_eq!(header_map.len(), 2);
+        let headers: Vec<(&str, &str)> = header_map.iter().collect();
+        assert_eq!(headers, vec![("ACCEPT", "application/json"), ("Host", "localhost")]);
+        header_map.insert("User-Agent", "test");
+        assert_eq!(header_map.get("user-agent"), Some("test"));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut header_map: HeaderMap = [("Vary", "Accept"), ("Host", "localhost"), ("vary", "Origin")].into_iter().collect();
+        assert_eq!(header_map.remove("VARY"), vec!["Accept".to_string(), "Origin".to_string()]);
+        assert_eq!(header_map.len(), 1);
+        assert!(!header_map.contains("Vary"));
+        assert!(header_map.remove("Vary").is_empty());
+    }
+
+    #[test]
+    fn test_get_combined() {
+        let header_map: HeaderMap = [("Vary", "Accept "), ("vary", " Origin")].into_iter().collect();
+        assert_eq!(header_map.get_combined("Vary"), Some("Accept, Origin".to_string()));
+        assert_eq!(header_map.get_combined("Host"), None);
+    }
+
+    #[test]
+    fn test_contains_token() {
+        let header_map: HeaderMap = [("Connection", "Upgrade, Close")].into_iter().collect();
+        assert!(header_map.contains_token("connection", "close"));
+        assert!(!header_map.contains_token("connection", "keep-alive"));
+        assert!(!header_map.contains_token("Upgrade", "close"));
+    }
+
+    #[test]
+    fn test_parse_field_line() {
+        assert_eq!(parse_field_line("Date: Tue, 15 Nov 1994 08:12:31 GMT").unwrap(), ("Date".to_string(), "Tue, 15 Nov 1994 08:12:31 GMT".to_string()));
+        assert_eq!(parse_field_line("Location:\thttp://localhost:8080/ \t").unwrap(), ("Location".to_string(), "http://localhost:8080/".to_string()));
+        assert_eq!(parse_field_line("X-Empty:").unwrap(), ("X-Empty".to_string(), "".to_string()));
+        assert_eq!(parse_field_line("X-Colon: a:b").unwrap().1, "a:b");
+    }
+
+    #[test]
+    fn test_parse_field_line_invalid() {
+        for line in ["No colon", "Name : value", "Name\t: value", " folded value", "\tfolded: value", ": value", "Bad Name: value", "Name: a\rb", "Name: a\0b"] {
+            let error = parse_field_line(line).err().unwrap();
+            assert_eq!(error.error_type, ClientErrorType::InvalidHeader, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_is_token() {
+        assert!(is_token("GET"));
+        assert!(is_token("X-Custom_Header.1"));
+        assert!(!is_token(""));
+        assert!(!is_token("Bad:Name"));
+        assert!(!is_token("Bad Name"));
+    }
+}
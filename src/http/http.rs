@@ -1,151 +1,740 @@
-use std::collections::HashMap;
+use std::fmt;
+use std::io::{ BufRead, BufReader, ErrorKind, Read };
+use std::sync::Arc;
 use std::time::Duration;
 
-use crate::connection::TcpConnection;
+use rustls::ClientConfig;
+
+use crate::connection::{ Connection, TcpConnection, TlsConnection };
 use crate::common::{ ClientError, ClientErrorType };
+use crate::http::header_map::{ is_token, HeaderMap };
+use crate::http::response::{ HttpResponse, ResponseHead };
+
+const HTTP_VERSION: &str = "HTTP/1.1";
+const CRLF: &str = "\r\n";
+const HOST: &str = "Host";
+const USER_AGENT: &str = "User-Agent";
+const DEFAULT_USER_AGENT: &str = concat!("http-client/", env!("CARGO_PKG_VERSION"));
+const CONNECTION: &str = "Connection";
+const CONTENT_LENGTH: &str = "Content-Length";
+const TRANSFER_ENCODING: &str = "Transfer-Encoding";
+const CHUNKED: &str = "chunked";
+const CHUNK_SIZE: usize = 8192;
+/**
+ * Headers with credentials, whose values are left out of the Debug output of a request.
+ */
+const REDACTED_HEADERS: [&str; 3] = ["Authorization", "Proxy-Authorization", "Cookie"];
+/**
+ * Methods where the request is expected to have a body.
+ */
+const METHODS_WITH_BODY: [&str; 3] = ["POST", "PUT", "PATCH"];
+
+/**
+ * Methods that are idempotent, and can be retried automatically when a reused connection fails.
+ */
+const IDEMPOTENT_METHODS: [&str; 6] = ["GET", "HEAD", "PUT", "DELETE", "OPTIONS", "TRACE"];
 
 /**
  * Http client.
- * Sends http requests.
+ * Sends http requests. The connection is kept open between requests unless the server
+ * or the request asks for it to be closed, and is reconnected when needed.
  */
 pub struct HttpClient {
-    tcp_connection: TcpConnection
+    connection: BufReader<Box<dyn Connection>>
 }
 
 impl HttpClient {
     pub fn new(host: String, port: u16, connection_timeout: Duration) -> HttpClient {
         let tcp_connection = TcpConnection::new(host, port, connection_timeout);
         HttpClient {
-            tcp_connection
+            connection: BufReader::new(Box::new(tcp_connection))
+        }
+    }
+
+    /**
+     * Creates a http client sending requests over tls.
+     * The server name is used for SNI and to verify the server certificate.
+     */
+    pub fn new_tls(host: String, port: u16, connection_timeout: Duration, server_name: String, client_config: Arc<ClientConfig>) -> HttpClient {
+        let tcp_connection = TcpConnection::new(host, port, connection_timeout);
+        HttpClient {
+            connection: BufReader::new(Box::new(TlsConnection::new(tcp_connection, server_name, client_config)))
+        }
+    }
+
+    /**
+     * Creates a http client sending requests over the connection.
+     */
+    pub fn from_connection(connection: Box<dyn Connection>) -> HttpClient {
+        HttpClient {
+            connection: BufReader::new(connection)
         }
     }
 
+    /**
+     * Returns the connection of the client. The connection is closed if it has unread data.
+     */
+    pub fn into_connection(mut self) -> Box<dyn Connection> {
+        if !self.connection.buffer().is_empty() {
+            self.close();
+        }
+        self.connection.into_inner()
+    }
+
     /**
      * Sends http request.
      * 
-     * If the tcp connection is not connected, the client will attempt to connect.
+     * If the connection is not connected, or the open connection has been closed by the server,
+     * the client will attempt to connect. If a reused connection fails before a response is
+     * received, an idempotent request without a streamed body is sent again on a new connection.
+     * The connection is closed after the response if the request or response asks for it.
      * Returns an error if the connection fails.
      */
-    pub fn send(mut self, http_request: HttpRequest) -> Result<HttpResponse, ClientError> {
-        if self.tcp_connection.is_not_connected() {
-            self.tcp_connection.connect()?
+    pub fn send(&mut self, mut http_request: HttpRequest) -> Result<HttpResponse, ClientError> {
+        let request_str: String = self.get_request_string(&http_request)?;
+        let reused = self.prepare_connection()?;
+        let mut result = self.exchange(&request_str, &mut http_request);
+        if reused && result.as_ref().is_err_and(|err| Self::is_retryable(&http_request, err)) {
+            self.close();
+            self.prepare_connection()?;
+            result = self.exchange(&request_str, &mut http_request);
+        }
+        match &result {
+            Ok(http_response) if http_response.is_persistent() && !http_request.headers.contains_token(CONNECTION, "close") => {},
+            _ => self.close()
+        }
+        result
+    }
+
+    /**
+     * Sends http requests pipelined on one connection.
+     *
+     * All requests are written back-to-back before the responses are read in order. Requests are
+     * not retried. If a response fails or closes the connection, the remaining requests get a
+     * PipelineAborted error, as the server may not have processed them. Returns an error if a
+     * request is invalid or the connection or write fails.
+     *
+     * The responses are only read after all requests are written, so the requests should fit in
+     * the socket buffers of the client and server.
+     */
+    pub fn send_pipelined(&mut self, http_requests: Vec<HttpRequest>) -> Result<Vec<Result<HttpResponse, ClientError>>, ClientError> {
+        let mut request_strs: Vec<String> = Vec::new();
+        for http_request in http_requests.iter() {
+            request_strs.push(self.get_request_string(http_request)?);
+        }
+        self.prepare_connection()?;
+        let mut sent_requests: Vec<(String, bool)> = Vec::new();
+        for (request_str, mut http_request) in request_strs.iter().zip(http_requests) {
+            let write_result = self.connection.get_mut().write(request_str.as_bytes())
+                .and_then(|_| self.write_body(&mut http_request.body));
+            if let Err(err) = write_result {
+                self.close();
+                return Err(err);
+            }
+            sent_requests.push((http_request.method, http_request.headers.contains_token(CONNECTION, "close")));
+        }
+        let mut http_responses: Vec<Result<HttpResponse, ClientError>> = Vec::new();
+        let mut aborted = false;
+        for (method, connection_close) in sent_requests.iter() {
+            if aborted {
+                http_responses.push(Err(ClientError::new(ClientErrorType::PipelineAborted, "Connection closed before the response".to_string())));
+                continue;
+            }
+            let result = HttpResponse::read(&mut self.connection, method).map(|mut http_response| {
+                http_response.tls_info = self.connection.get_ref().get_tls_info();
+                http_response
+            });
+            aborted = *connection_close || !matches!(&result, Ok(http_response) if http_response.is_persistent());
+            http_responses.push(result);
+        }
+        if aborted {
+            self.close();
+        }
+        Ok(http_responses)
+    }
+
+    /**
+     * Returns true if the client does not have an open connection.
+     */
+    pub fn is_not_connected(&self) -> bool {
+        self.connection.get_ref().is_not_connected()
+    }
+
+    /**
+     * Closes the connection. The next request will open a new connection.
+     */
+    pub fn close(&mut self) {
+        let buffered = self.connection.buffer().len();
+        self.connection.consume(buffered);
+        self.connection.get_mut().close();
+    }
+
+    /**
+     * Makes sure the client has a usable connection.
+     * An open connection is reused unless the server has closed it or sent unexpected data.
+     *
+     * Returns true if an open connection is reused.
+     */
+    fn prepare_connection(&mut self) -> Result<bool, ClientError> {
+        if !self.is_not_connected() {
+            if self.connection.buffer().is_empty() && !self.connection.get_mut().is_stale() {
+                return Ok(true);
+            }
+            self.close();
         }
-        let request_str: String = self.get_request_string(&http_request);
-        let _ = &self.tcp_connection.write(&request_str)?;
-        let read_result = &self.tcp_connection.read()?;
-        if read_result.len() > 0 {
-            HttpResponse::new(&read_result)
-        } else {
-            Err(ClientError::new(ClientErrorType::NoResponse,"No response".to_string()))
-        }       
+        self.connection.get_mut().connect()?;
+        Ok(false)
+    }
+
+    /**
+     * Writes the request and reads the response.
+     */
+    fn exchange(&mut self, request_str: &str, http_request: &mut HttpRequest) -> Result<HttpResponse, ClientError> {
+        self.connection.get_mut().write(request_str.as_bytes())?;
+        self.write_body(&mut http_request.body)?;
+        let mut http_response = HttpResponse::read(&mut self.connection, &http_request.method)?;
+        http_response.tls_info = self.connection.get_ref().get_tls_info();
+        Ok(http_response)
+    }
+
+    /**
+     * Returns true if the request can be sent again after the error.
+     * Only errors where the server closed the connection without responding are retried,
+     * and only for idempotent requests where the body can be sent again.
+     */
+    fn is_retryable(http_request: &HttpRequest, err: &ClientError) -> bool {
+        matches!(err.error_type, ClientErrorType::NoResponse | ClientErrorType::WriteError | ClientErrorType::ConnectionReset)
+            && IDEMPOTENT_METHODS.contains(&http_request.method.to_ascii_uppercase().as_str())
+            && !matches!(http_request.body, Some(RequestBody::Stream(_)))
     }
 
     /**
      * Get request string from http request.
-     * Example: GET / HTTP/1.1\nHost: localhost\n\n
+     * The request string contains the request line and the header fields, each terminated by CRLF,
+     * followed by an empty line. Host and User-Agent are added unless supplied by the user, and the
+     * framing header for the body is added last. The body itself is written separately.
+     * Example: GET / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: http-client/0.1.0\r\n\r\n
+     *
+     * Returns an error if the request line or a header is invalid, or if the user supplied
+     * framing headers conflict with the body.
      */
-    pub fn get_request_string(&self, http_request: &HttpRequest) -> String {
+    pub fn get_request_string(&self, http_request: &HttpRequest) -> Result<String, ClientError> {
+        Self::validate_request_line(http_request)?;
+        let framing_header = Self::get_framing_header(http_request)?;
+        let mut headers = HeaderMap::new();
+        if !http_request.headers.contains(HOST) {
+            headers.append(HOST, &http_request.host);
+        }
+        if !http_request.headers.contains(USER_AGENT) {
+            headers.append(USER_AGENT, DEFAULT_USER_AGENT);
+        }
+        headers.extend(http_request.headers.iter());
+        headers.extend(framing_header);
         let mut request_string = http_request.method.clone();
-        request_string.push_str( " ");
-        request_string.push_str(&http_request.path.clone());
-        request_string.push_str(" HTTP/1.1\n");
-        http_request.headers.iter().for_each(|header| { 
+        request_string.push(' ');
+        request_string.push_str(&http_request.path);
+        request_string.push(' ');
+        request_string.push_str(HTTP_VERSION);
+        request_string.push_str(CRLF);
+        for header in headers.iter() {
+            Self::validate_header(header.0, header.1)?;
             request_string.push_str(header.0);
             request_string.push_str(": ");
-            request_string.push_str(header.1);
-        });
-        request_string.push_str("\n\n");
-        request_string
+            request_string.push_str(header.1.trim());
+            request_string.push_str(CRLF);
+        }
+        request_string.push_str(CRLF);
+        Ok(request_string)
+    }
+
+    /**
+     * Validates the method and request target.
+     * The method must be a token and the request target must not be empty or contain whitespace
+     * or control characters.
+     */
+    fn validate_request_line(http_request: &HttpRequest) -> Result<(), ClientError> {
+        if !is_token(&http_request.method) {
+            return Err(ClientError::new(ClientErrorType::InvalidRequest, format!("Invalid method {:?}", http_request.method)));
+        }
+        if http_request.path.is_empty() || http_request.path.chars().any(|character| character.is_whitespace() || character.is_control()) {
+            return Err(ClientError::new(ClientErrorType::InvalidRequest, format!("Invalid request target {:?}", http_request.path)));
+        }
+        Ok(())
     }
 
+    /**
+     * Validates a header field.
+     * The name must be a token and the value must not contain CR, LF or NUL,
+     * as these would allow splitting the request.
+     */
+    fn validate_header(name: &str, value: &str) -> Result<(), ClientError> {
+        if !is_token(name) {
+            return Err(ClientError::new(ClientErrorType::InvalidRequest, format!("Invalid header name {:?}", name)));
+        }
+        if value.chars().any(|character| matches!(character, '\r' | '\n' | '\0')) {
+            return Err(ClientError::new(ClientErrorType::InvalidRequest, format!("Invalid value for header {}", name)));
+        }
+        Ok(())
+    }
+
+    /**
+     * Get the framing header to add for the request body.
+     * Bodies with a known length get a Content-Length header, streamed bodies are sent chunked.
+     * Requests without a body get Content-Length: 0 when the method expects a body.
+     *
+     * Returns None if no header should be added, either because none is needed or because
+     * the user supplied an equivalent one, whatever the case of its name. Returns an error if
+     * the user supplied framing headers conflict with the body or Content-Length is invalid.
+     */
+    fn get_framing_header(http_request: &HttpRequest) -> Result<Option<(String, String)>, ClientError> {
+        let content_length = http_request.headers.get_combined(CONTENT_LENGTH);
+        let transfer_encoding = http_request.headers.get_combined(TRANSFER_ENCODING);
+        if content_length.is_some() && transfer_encoding.is_some() {
+            return Err(Self::framing_error("Both Content-Length and Transfer-Encoding headers are set"));
+        }
+        match &http_request.body {
+            Some(RequestBody::Bytes(bytes)) => {
+                if transfer_encoding.is_some() {
+                    return Err(Self::framing_error("Transfer-Encoding header is not supported for a body with known length"));
+                }
+                match content_length {
+                    Some(content_length) if ResponseHead::parse_content_length(&content_length)? != bytes.len() as u64 => Err(Self::framing_error("Content-Length header does not match the body length")),
+                    Some(_) => Ok(None),
+                    None => Ok(Some((CONTENT_LENGTH.to_string(), bytes.len().to_string())))
+                }
+            },
+            Some(RequestBody::Stream(_)) => {
+                if content_length.is_some() {
+                    return Err(Self::framing_error("Content-Length header is not supported for a streamed body"));
+                }
+                match transfer_encoding {
+                    Some(transfer_encoding) if !transfer_encoding.eq_ignore_ascii_case(CHUNKED) => Err(Self::framing_error("Only chunked Transfer-Encoding is supported for a streamed body")),
+                    Some(_) => Ok(None),
+                    None => Ok(Some((TRANSFER_ENCODING.to_string(), CHUNKED.to_string())))
+                }
+            },
+            None => {
+                if transfer_encoding.is_some() {
+                    return Err(Self::framing_error("Transfer-Encoding header is set without a body"));
+                }
+                match content_length {
+                    Some(content_length) if ResponseHead::parse_content_length(&content_length)? != 0 => Err(Self::framing_error("Content-Length header is set without a body")),
+                    Some(_) => Ok(None),
+                    None if METHODS_WITH_BODY.contains(&http_request.method.to_ascii_uppercase().as_str()) => Ok(Some((CONTENT_LENGTH.to_string(), "0".to_string()))),
+                    None => Ok(None)
+                }
+            }
+        }
+    }
+
+    fn framing_error(message: &str) -> ClientError {
+        ClientError::new(ClientErrorType::ConflictingFramingHeaders, message.to_string())
+    }
+
+    /**
+     * Writes the request body to the connection.
+     * Streamed bodies are written using chunked transfer coding.
+     */
+    fn write_body(&mut self, body: &mut Option<RequestBody>) -> Result<(), ClientError> {
+        let connection = self.connection.get_mut();
+        match body {
+            Some(RequestBody::Bytes(bytes)) => connection.write(bytes),
+            Some(RequestBody::Stream(reader)) => {
+                let mut buffer = [0u8; CHUNK_SIZE];
+                loop {
+                    let read = match reader.read(&mut buffer) {
+                        Ok(read) => read,
+                        Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                        Err(err) => return Err(ClientError::new(ClientErrorType::ReadError, err.to_string()))
+                    };
+                    if read == 0 {
+                        break;
+                    }
+                    connection.write(&Self::get_chunk(&buffer[..read]))?;
+                }
+                connection.write(b"0\r\n\r\n")
+            },
+            None => Ok(())
+        }
+    }
+
+    /**
+     * Get a chunk in chunked transfer coding: size in hex, the data and a line ending.
+     */
+    fn get_chunk(data: &[u8]) -> Vec<u8> {
+        let mut chunk = format!("{:X}\r\n", data.len()).into_bytes();
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(b"\r\n");
+        chunk
+    }
+
+}
+
+/**
+ * Request body.
+ * A body is either held in memory with a known length or streamed from a reader
+ * with an unknown length.
+ */
+pub enum RequestBody {
+    Bytes(Vec<u8>),
+    Stream(Box<dyn Read + Send>)
 }
 
-#[derive(Debug)]
+impl RequestBody {
+    /**
+     * Creates a request body from a string.
+     */
+    pub fn from_string(body: String) -> RequestBody {
+        RequestBody::Bytes(body.into_bytes())
+    }
+
+    /**
+     * Returns a copy of the body, or None if the body is streamed and can only be read once.
+     */
+    pub fn try_clone(&self) -> Option<RequestBody> {
+        match self {
+            RequestBody::Bytes(bytes) => Some(RequestBody::Bytes(bytes.clone())),
+            RequestBody::Stream(_) => None
+        }
+    }
+}
+
+impl fmt::Debug for RequestBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestBody::Bytes(bytes) => write!(f, "Bytes({:?})", String::from_utf8_lossy(bytes)),
+            RequestBody::Stream(_) => write!(f, "Stream")
+        }
+    }
+}
+
+/**
+ * Http request.
+ * The host is the authority of the target uri, host and port if it is not the default port,
+ * and is sent in the Host header.
+ * The values of credential headers are left out of the Debug output.
+ */
 pub struct HttpRequest {
     pub path: String,
     pub method: String,
-    pub headers: HashMap<String, String>,
-    pub body: Option<String>
+    pub host: String,
+    pub headers: HeaderMap,
+    pub body: Option<RequestBody>
 }
 
 impl HttpRequest {
-    pub fn new(path: String, method: String, headers: HashMap<String, String>, body: Option<String>) -> HttpRequest {
+    pub fn new(path: String, method: String, host: String, headers: HeaderMap, body: Option<RequestBody>) -> HttpRequest {
         HttpRequest {
             path,
             method,
+            host,
             headers,
             body
         }
     }
 }
 
-#[derive(Debug)]
-pub struct HttpResponse {
-    pub response_code: u16,
-    pub headers: HashMap<String, String>,
-    pub body: Option<String>
+impl fmt::Debug for HttpRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let headers: HeaderMap = self.headers
+            .iter()
+            .map(|(name, value)| if REDACTED_HEADERS.iter().any(|redacted| redacted.eq_ignore_ascii_case(name)) { (name, "<redacted>") } else { (name, value) })
+            .collect();
+        f.debug_struct("HttpRequest")
+            .field("path", &self.path)
+            .field("method", &self.method)
+            .field("host", &self.host)
+            .field("headers", &headers)
+            .field("body", &self.body)
+            .finish()
+    }
 }
 
-impl HttpResponse {
-    pub fn new(response: &String) -> Result<HttpResponse, ClientError> {
-        let lines: std::str::Lines = response.lines();
-        let mut response_code = 500;
-        let mut headers: HashMap<String, String> = HashMap::new();
-        let mut body: Option<String> = None; 
-
-        let mut body_start = false;
-
-        for (index,line) in lines.enumerate() {
-            if index == 0 {
-                response_code = Self::get_response_code(&line);
-            }            
-            if line.is_empty() {
-                body_start = true;
-            } else if index > 0{
-                if body_start {
-                    body = Self::append_body(body, line.to_string());
-                } else {
-                    let header = Self::get_header(&line);
-                    headers.insert(header.0, header.1);
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{ BufRead, Write };
+    use std::net::{ TcpListener, TcpStream };
+    use std::sync::mpsc;
+    use std::thread;
+
+    /**
+     * Starts a server accepting the given number of connections. Each connection gets the
+     * responses in order, one for each request read. Returns the port and a handle returning
+     * the requests received on each connection.
+     */
+    fn start_server(connections: Vec<Vec<&'static str>>) -> (u16, thread::JoinHandle<Vec<Vec<String>>>) {
+        let (port, handle, _) = start_server_notifying(connections);
+        (port, handle)
+    }
+
+    /**
+     * Starts a server like start_server, also returning a receiver getting the index of each
+     * connection once the server has closed it.
+     */
+    fn start_server_notifying(connections: Vec<Vec<&'static str>>) -> (u16, thread::JoinHandle<Vec<Vec<String>>>, mpsc::Receiver<usize>) {
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = tcp_listener.local_addr().unwrap().port();
+        let (closed_sender, closed_receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            connections.into_iter().enumerate().map(|(index, responses)| {
+                let (tcp_stream, _) = tcp_listener.accept().unwrap();
+                let requests = serve_connection(tcp_stream, responses);
+                let _ = closed_sender.send(index);
+                requests
+            }).collect()
+        });
+        (port, handle, closed_receiver)
+    }
+
+    fn serve_connection(mut tcp_stream: TcpStream, responses: Vec<&'static str>) -> Vec<String> {
+        let mut reader = std::io::BufReader::new(tcp_stream.try_clone().unwrap());
+        let mut requests = Vec::new();
+        for response in responses {
+            let mut request = String::new();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    return requests;
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
                 }
             }
+            requests.push(request);
+            tcp_stream.write_all(response.as_bytes()).unwrap();
         }
+        requests
+    }
 
-        Ok(HttpResponse {
-            response_code,
-            headers,
-            body
-        })
+    fn get_request(path: &str) -> HttpRequest {
+        HttpRequest::new(path.to_string(), "GET".to_string(), "localhost".to_string(), HeaderMap::new(), None)
     }
 
-    /**
-     * Get response code from first line of response.
-     * If the response code cannot be parsed, the default is 500.
-     * The default is 500 because the response code is required.
-     * If the response code is not provided, the server is not following the http protocol.
-     */
-    fn get_response_code(line: &str) -> u16 {
-        line.split_whitespace().nth(1).unwrap_or("500").parse::<u16>().unwrap_or(500)
+    fn get_http_client() -> HttpClient {
+        HttpClient::new("localhost".to_string(), 8080, Duration::from_secs(5))
     }
-    /**
-     * Get header from line. 
-     */
-    fn get_header(line: &str) -> (String, String) {
-        let header_parts: Vec<&str> = line.split(":").collect();
-        (header_parts[0].to_string(), header_parts[1].to_string())
+
+    fn get_headers(headers: &[(&str, &str)]) -> HeaderMap {
+        headers.iter().copied().collect()
     }
 
-    /**
-     * Append line to body.
-     */
-    fn append_body(body: Option<String>, line: String) -> Option<String> {
-        match body {
-            Some(body) => {
-                let mut new_body = body.clone();
-                new_body.push_str(&line);
-                Some(new_body)
-            },
-            None => Some(line)
+    #[test]
+    fn test_get_request_string_content_length() {
+        let http_request = HttpRequest::new("/".to_string(), "POST".to_string(), "localhost".to_string(), HeaderMap::new(), Some(RequestBody::from_string("{}".to_string())));
+        let request_string = get_http_client().get_request_string(&http_request).unwrap();
+        assert_eq!(request_string, format!("POST / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: {}\r\nContent-Length: 2\r\n\r\n", DEFAULT_USER_AGENT));
+    }
+
+    #[test]
+    fn test_get_request_string_user_content_length() {
+        for content_length in ["2", "2, 2"] {
+            let headers = get_headers(&[("content-length", content_length)]);
+            let http_request = HttpRequest::new("/".to_string(), "POST".to_string(), "localhost".to_string(), headers, Some(RequestBody::from_string("{}".to_string())));
+            let request_string = get_http_client().get_request_string(&http_request).unwrap();
+            assert_eq!(request_string.to_ascii_lowercase().matches("content-length").count(), 1);
+        }
+        let headers = get_headers(&[("content-length", "two")]);
+        let http_request = HttpRequest::new("/".to_string(), "POST".to_string(), "localhost".to_string(), headers, Some(RequestBody::from_string("{}".to_string())));
+        assert_eq!(get_http_client().get_request_string(&http_request).err().unwrap().error_type, ClientErrorType::InvalidContentLength);
+    }
+
+    #[test]
+    fn test_get_request_string_chunked() {
+        let http_request = HttpRequest::new("/".to_string(), "POST".to_string(), "localhost".to_string(), HeaderMap::new(), Some(RequestBody::Stream(Box::new(std::io::empty()))));
+        let request_string = get_http_client().get_request_string(&http_request).unwrap();
+        assert_eq!(request_string, format!("POST / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: {}\r\nTransfer-Encoding: chunked\r\n\r\n", DEFAULT_USER_AGENT));
+    }
+
+    #[test]
+    fn test_get_request_string_get() {
+        let http_request = HttpRequest::new("/index.html".to_string(), "GET".to_string(), "localhost:8080".to_string(), HeaderMap::new(), None);
+        let request_string = get_http_client().get_request_string(&http_request).unwrap();
+        assert_eq!(request_string, format!("GET /index.html HTTP/1.1\r\nHost: localhost:8080\r\nUser-Agent: {}\r\n\r\n", DEFAULT_USER_AGENT));
+    }
+
+    #[test]
+    fn test_get_request_string_user_headers() {
+        let headers = get_headers(&[("host", "example.com"), ("user-agent", "curl/8.0")]);
+        let http_request = HttpRequest::new("/".to_string(), "GET".to_string(), "localhost".to_string(), headers, None);
+        let request_string = get_http_client().get_request_string(&http_request).unwrap();
+        assert!(request_string.starts_with("GET / HTTP/1.1\r\n"));
+        assert!(request_string.contains("\r\nhost: example.com\r\n"));
+        assert!(request_string.contains("\r\nuser-agent: curl/8.0\r\n"));
+        assert!(!request_string.contains("Host: localhost"));
+        assert!(!request_string.contains(DEFAULT_USER_AGENT));
+        assert!(request_string.ends_with("\r\n\r\n"));
+        assert_eq!(request_string.matches("\r\n").count(), 4);
+    }
+
+    #[test]
+    fn test_get_request_string_trims_header_value() {
+        let headers = get_headers(&[("Accept", " application/json ")]);
+        let http_request = HttpRequest::new("/".to_string(), "GET".to_string(), "localhost".to_string(), headers, None);
+        let request_string = get_http_client().get_request_string(&http_request).unwrap();
+        assert_eq!(request_string, format!("GET / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: {}\r\nAccept: application/json\r\n\r\n", DEFAULT_USER_AGENT));
+    }
+
+    #[test]
+    fn test_get_request_string_invalid() {
+        let invalid_requests = [
+            HttpRequest::new("/".to_string(), "GE T".to_string(), "localhost".to_string(), HeaderMap::new(), None),
+            HttpRequest::new("/a b".to_string(), "GET".to_string(), "localhost".to_string(), HeaderMap::new(), None),
+            HttpRequest::new("".to_string(), "GET".to_string(), "localhost".to_string(), HeaderMap::new(), None),
+            HttpRequest::new("/".to_string(), "GET".to_string(), "localhost".to_string(), get_headers(&[("Bad Name", "value")]), None),
+            HttpRequest::new("/".to_string(), "GET".to_string(), "localhost".to_string(), get_headers(&[("X-Injected", "value\r\nEvil: header")]), None),
+        ];
+        for http_request in invalid_requests {
+            let error = get_http_client().get_request_string(&http_request).err().unwrap();
+            assert_eq!(error.error_type, ClientErrorType::InvalidRequest);
+        }
+    }
+
+    #[test]
+    fn test_get_framing_header_no_body() {
+        let http_request = HttpRequest::new("/".to_string(), "GET".to_string(), "localhost".to_string(), HeaderMap::new(), None);
+        assert_eq!(HttpClient::get_framing_header(&http_request).unwrap(), None);
+        let http_request = HttpRequest::new("/".to_string(), "post".to_string(), "localhost".to_string(), HeaderMap::new(), None);
+        assert_eq!(HttpClient::get_framing_header(&http_request).unwrap(), Some((CONTENT_LENGTH.to_string(), "0".to_string())));
+    }
+
+    #[test]
+    fn test_get_framing_header_matching_user_header() {
+        let headers = get_headers(&[("content-length", "2")]);
+        let http_request = HttpRequest::new("/".to_string(), "POST".to_string(), "localhost".to_string(), headers, Some(RequestBody::from_string("{}".to_string())));
+        assert_eq!(HttpClient::get_framing_header(&http_request).unwrap(), None);
+        let headers = get_headers(&[("Transfer-Encoding", "Chunked")]);
+        let http_request = HttpRequest::new("/".to_string(), "POST".to_string(), "localhost".to_string(), headers, Some(RequestBody::Stream(Box::new(std::io::empty()))));
+        assert_eq!(HttpClient::get_framing_header(&http_request).unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_framing_header_conflicts() {
+        let conflicts = [
+            (get_headers(&[("Content-Length", "3")]), Some(RequestBody::from_string("{}".to_string()))),
+            (get_headers(&[("Transfer-Encoding", "chunked")]), Some(RequestBody::from_string("{}".to_string()))),
+            (get_headers(&[("Content-Length", "2"), ("Transfer-Encoding", "chunked")]), Some(RequestBody::from_string("{}".to_string()))),
+            (get_headers(&[("Content-Length", "2")]), Some(RequestBody::Stream(Box::new(std::io::empty())))),
+            (get_headers(&[("Transfer-Encoding", "gzip")]), Some(RequestBody::Stream(Box::new(std::io::empty())))),
+            (get_headers(&[("Content-Length", "2")]), None),
+            (get_headers(&[("Transfer-Encoding", "chunked")]), None),
+        ];
+        for (headers, body) in conflicts {
+            let http_request = HttpRequest::new("/".to_string(), "POST".to_string(), "localhost".to_string(), headers, body);
+            let error = HttpClient::get_framing_header(&http_request).err().unwrap();
+            assert_eq!(error.error_type, ClientErrorType::ConflictingFramingHeaders);
         }
     }
+
+    #[test]
+    fn test_send_reuses_connection() {
+        let (port, handle) = start_server(vec![vec!["HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfirst", "HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nsecond"]]);
+        let mut http_client = HttpClient::new("127.0.0.1".to_string(), port, Duration::from_secs(5));
+        assert_eq!(http_client.send(get_request("/first")).unwrap().body, b"first");
+        assert!(!http_client.is_not_connected());
+        assert_eq!(http_client.send(get_request("/second")).unwrap().body, b"second");
+        http_client.close();
+        let requests = handle.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].len(), 2);
+        assert!(requests[0][1].starts_with("GET /second HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_send_connection_close() {
+        let (port, handle) = start_server(vec![vec!["HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nfirst"], vec!["HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nsecond"]]);
+        let mut http_client = HttpClient::new("127.0.0.1".to_string(), port, Duration::from_secs(5));
+        assert_eq!(http_client.send(get_request("/first")).unwrap().body, b"first");
+        assert!(http_client.is_not_connected());
+        let mut http_request = get_request("/second");
+        http_request.headers.insert("Connection", "close");
+        assert_eq!(http_client.send(http_request).unwrap().body, b"second");
+        assert!(http_client.is_not_connected());
+        let requests = handle.join().unwrap();
+        assert_eq!(requests.len(), 2);
+    }
+
+    #[test]
+    fn test_send_reconnects_stale_connection() {
+        let (port, handle, closed) = start_server_notifying(vec![vec!["HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfirst"], vec!["HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nsecond"]]);
+        let mut http_client = HttpClient::new("127.0.0.1".to_string(), port, Duration::from_secs(5));
+        assert_eq!(http_client.send(get_request("/first")).unwrap().body, b"first");
+        // The server closes the first connection after the response, waiting for the second connection.
+        assert_eq!(closed.recv().unwrap(), 0);
+        assert_eq!(http_client.send(get_request("/second")).unwrap().body, b"second");
+        let requests = handle.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1][0].starts_with("GET /second HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_send_pipelined() {
+        let (port, handle) = start_server(vec![vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfirst",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nsecond\r\n0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nthird"
+        ]]);
+        let mut http_client = HttpClient::new("127.0.0.1".to_string(), port, Duration::from_secs(5));
+        let http_requests = vec![get_request("/first"), get_request("/second"), get_request("/third")];
+        let http_responses = http_client.send_pipelined(http_requests).unwrap();
+        let bodies: Vec<Vec<u8>> = http_responses.into_iter().map(|http_response| http_response.unwrap().body).collect();
+        assert_eq!(bodies, vec![b"first".to_vec(), b"second".to_vec(), b"third".to_vec()]);
+        assert!(!http_client.is_not_connected());
+        http_client.close();
+        let requests = handle.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].len(), 3);
+    }
+
+    #[test]
+    fn test_send_pipelined_connection_closed() {
+        let (port, handle) = start_server(vec![vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nfirst",
+        ]]);
+        let mut http_client = HttpClient::new("127.0.0.1".to_string(), port, Duration::from_secs(5));
+        let http_requests = vec![get_request("/first"), get_request("/second")];
+        let http_responses = http_client.send_pipelined(http_requests).unwrap();
+        assert_eq!(http_responses[0].as_ref().unwrap().body, b"first");
+        assert_eq!(http_responses[1].as_ref().err().unwrap().error_type, ClientErrorType::PipelineAborted);
+        assert!(http_client.is_not_connected());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_try_clone() {
+        let body = RequestBody::from_string("{}".to_string());
+        assert!(matches!(body.try_clone(), Some(RequestBody::Bytes(bytes)) if bytes == b"{}"));
+        assert!(RequestBody::Stream(Box::new(std::io::empty())).try_clone().is_none());
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let no_response = ClientError::new(ClientErrorType::NoResponse, "No response".to_string());
+        assert!(HttpClient::is_retryable(&get_request("/"), &no_response));
+        let post_request = HttpRequest::new("/".to_string(), "POST".to_string(), "localhost".to_string(), HeaderMap::new(), None);
+        assert!(!HttpClient::is_retryable(&post_request, &no_response));
+        let stream_request = HttpRequest::new("/".to_string(), "PUT".to_string(), "localhost".to_string(), HeaderMap::new(), Some(RequestBody::Stream(Box::new(std::io::empty()))));
+        assert!(!HttpClient::is_retryable(&stream_request, &no_response));
+        let invalid_status_line = ClientError::new(ClientErrorType::InvalidStatusLine, "Invalid status line".to_string());
+        assert!(!HttpClient::is_retryable(&get_request("/"), &invalid_status_line));
+    }
+
+    #[test]
+    fn test_debug_redacts_credentials() {
+        let headers = get_headers(&[("Authorization", "Basic dXNlcjpwYXNz"), ("proxy-authorization", "Basic cHJveHk6cGFzcw=="), ("Cookie", "session=secret"), ("Accept", "*/*")]);
+        let http_request = HttpRequest::new("/".to_string(), "GET".to_string(), "localhost".to_string(), headers, None);
+        let debug = format!("{:?}", http_request);
+        assert!(debug.contains("(\"Authorization\", \"<redacted>\")"));
+        assert!(debug.contains("(\"proxy-authorization\", \"<redacted>\")"));
+        assert!(debug.contains("(\"Cookie\", \"<redacted>\")"));
+        assert!(debug.contains("(\"Accept\", \"*/*\")"));
+        assert!(!debug.contains("dXNlcjpwYXNz") && !debug.contains("cHJveHk6cGFzcw==") && !debug.contains("secret"));
+        assert_eq!(http_request.headers.get("Authorization"), Some("Basic dXNlcjpwYXNz"));
+    }
+
+    #[test]
+    fn test_get_chunk() {
+        assert_eq!(HttpClient::get_chunk(b"Hello world, hello world"), b"18\r\nHello world, hello world\r\n");
+    }
 }
@@ -1,4 +1,12 @@
+#[allow(clippy::module_inception)]
 mod http;
+mod content_digest;
+mod header_map;
+pub(crate) mod parser;
+mod response;
+pub(crate) mod structured_field;
 
-pub use { http::HttpClient, http::HttpRequest };
-
+pub use content_digest::{ add_content_digest, add_want_digest, get_digest_value, verify_response_digests, HashAlgorithm, VerifiedDigest };
+pub use header_map::{ parse_field_line, HeaderMap };
+pub use { http::HttpClient, http::HttpRequest, http::RequestBody };
+pub use response::{ BodyFraming, BodyReader, HttpResponse, ResponseHead };
@@ -0,0 +1,67 @@
+/**
+ * Parser over the bytes of a field value, shared by the parsers of the different field syntaxes.
+ * Each parser adds its own methods in an impl block of its module.
+ */
+pub(crate) struct Parser<'a> {
+    pub(crate) input: &'a [u8],
+    pub(crate) position: usize
+}
+
+impl Parser<'_> {
+    pub(crate) fn new(value: &str) -> Parser<'_> {
+        Parser { input: value.as_bytes(), position: 0 }
+    }
+
+    pub(crate) fn is_end(&self) -> bool {
+        self.position >= self.input.len()
+    }
+
+    pub(crate) fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+
+    /**
+     * Consumes the byte if the input is at it. Returns false if it is not.
+     */
+    pub(crate) fn consume(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    pub(crate) fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.position += 1;
+        }
+    }
+
+    pub(crate) fn read_while(&mut self, predicate: fn(u8) -> bool) -> String {
+        let start = self.position;
+        while self.peek().is_some_and(predicate) {
+            self.position += 1;
+        }
+        String::from_utf8_lossy(&self.input[start..self.position]).to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parser() {
+        let mut parser = Parser::new(" \tabc12=x");
+        parser.skip_whitespace();
+        assert_eq!(parser.read_while(|byte| byte.is_ascii_alphabetic()), "abc");
+        assert_eq!(parser.read_while(|byte| byte.is_ascii_alphabetic()), "");
+        assert_eq!(parser.peek(), Some(b'1'));
+        assert!(!parser.consume(b'='));
+        assert_eq!(parser.read_while(|byte| byte.is_ascii_digit()), "12");
+        assert!(parser.consume(b'='));
+        assert!(parser.consume(b'x'));
+        assert!(parser.is_end());
+        assert_eq!(parser.peek(), None);
+    }
+}
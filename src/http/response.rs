@@ -0,0 +1,700 @@
+use std::fmt;
+use std::io::{ BufRead, ErrorKind, Read };
+
+use crate::common::{ ClientError, ClientErrorType };
+use crate::connection::TlsInfo;
+use crate::http::header_map::{ parse_field_line, HeaderMap };
+
+/**
+ * Maximum length of the status line or a header line.
+ */
+const MAX_LINE_LENGTH: usize = 8192;
+/**
+ * Maximum size of the response header section.
+ */
+const MAX_HEADER_SECTION_SIZE: usize = 65536;
+/**
+ * Maximum number of interim 1xx responses skipped before the final response.
+ */
+const MAX_INTERIM_RESPONSES: usize = 10;
+const CONNECTION: &str = "Connection";
+const CONTENT_LENGTH: &str = "Content-Length";
+const TRANSFER_ENCODING: &str = "Transfer-Encoding";
+const CHUNKED: &str = "chunked";
+
+/**
+ * Http response.
+ * The body is kept as bytes, as responses are not necessarily utf-8.
+ * Trailers are the header fields sent after a chunked body.
+ * The tls info is the result of the handshake of the connection the response was read from,
+ * and is left out of the Debug output.
+ */
+pub struct HttpResponse {
+    pub version: String,
+    pub response_code: u16,
+    pub reason: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    pub trailers: HeaderMap,
+    pub tls_info: Option<TlsInfo>,
+    persistent: bool
+}
+
+impl HttpResponse {
+    /**
+     * Reads a complete response to a request with the given method from the reader.
+     * Returns an error if the response cannot be parsed or read.
+     */
+    pub fn read<R: BufRead>(reader: &mut R, method: &str) -> Result<HttpResponse, ClientError> {
+        let response_head = ResponseHead::read(reader)?;
+        let mut body: Vec<u8> = Vec::new();
+        let mut body_reader = response_head.body_reader(reader, method)?;
+        if let Err(err) = body_reader.read_to_end(&mut body) {
+            return Err(get_body_error(err));
+        }
+        let persistent = response_head.is_persistent(&body_reader.body_framing);
+        let trailers = body_reader.trailers;
+        Ok(HttpResponse {
+            version: response_head.version,
+            response_code: response_head.response_code,
+            reason: response_head.reason,
+            headers: response_head.headers,
+            body,
+            trailers,
+            tls_info: None,
+            persistent
+        })
+    }
+
+    /**
+     * Returns true if the connection can be used for another request after this response.
+     */
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    /**
+     * Returns the body as a string. Invalid utf-8 sequences are replaced.
+     */
+    pub fn get_body_string(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+}
+
+impl fmt::Debug for HttpResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpResponse")
+            .field("version", &self.version)
+            .field("response_code", &self.response_code)
+            .field("reason", &self.reason)
+            .field("headers", &self.headers)
+            .field("body", &self.get_body_string())
+            .field("trailers", &self.trailers)
+            .finish()
+    }
+}
+
+/**
+ * Status line and headers of a http response.
+ * Parsed incrementally from a reader, leaving the reader positioned at the start of the body.
+ */
+#[derive(Debug)]
+pub struct ResponseHead {
+    pub version: String,
+    pub response_code: u16,
+    pub reason: String,
+    pub headers: HeaderMap
+}
+
+impl ResponseHead {
+    /**
+     * Reads the status line and headers from the reader.
+     * Interim 1xx responses, except 101 Switching Protocols, are skipped.
+     * Returns an error if the server sends no response, the head cannot be parsed or the server
+     * sends more than MAX_INTERIM_RESPONSES interim responses.
+     */
+    pub fn read<R: BufRead>(reader: &mut R) -> Result<ResponseHead, ClientError> {
+        for _ in 0..=MAX_INTERIM_RESPONSES {
+            let response_head = Self::read_head(reader)?;
+            if !(100..200).contains(&response_head.response_code) || response_head.response_code == 101 {
+                return Ok(response_head);
+            }
+        }
+        Err(ClientError::new(ClientErrorType::TooManyInterimResponses, format!("More than {} interim responses", MAX_INTERIM_RESPONSES)))
+    }
+
+    /**
+     * Returns a reader for the body following the head of a response to a request with the given method.
+     * The body length is determined as described in RFC 9112 section 6.3.
+     * Returns an error if the Content-Length header is invalid.
+     */
+    pub fn body_reader<'a, R: BufRead>(&self, reader: &'a mut R, method: &str) -> Result<BodyReader<'a, R>, ClientError> {
+        let body_framing = self.get_body_framing(method)?;
+        Ok(BodyReader::new(reader, body_framing))
+    }
+
+    /**
+     * Returns true if the connection can be used for another request after this response,
+     * as described in RFC 9112 section 9.3.
+     * The connection is not persistent if the server asks to close it, if a HTTP/1.0 server does
+     * not ask to keep it alive, if the body is delimited by closing the connection, or if both
+     * Transfer-Encoding and Content-Length are sent.
+     */
+    pub fn is_persistent(&self, body_framing: &BodyFraming) -> bool {
+        if *body_framing == BodyFraming::Close || self.headers.contains_token(CONNECTION, "close") {
+            return false;
+        }
+        if self.headers.contains(TRANSFER_ENCODING) && self.headers.contains(CONTENT_LENGTH) {
+            return false;
+        }
+        if self.version == "HTTP/1.0" {
+            return self.headers.contains_token(CONNECTION, "keep-alive");
+        }
+        true
+    }
+
+    /**
+     * Get the framing of the body.
+     * Responses to HEAD, successful responses to CONNECT and 1xx, 204 and 304 responses have no body.
+     * If Transfer-Encoding is present, the body is chunked if chunked is the final coding and
+     * otherwise read until the connection is closed. Transfer-Encoding overrides Content-Length.
+     * Without either header the body is read until the connection is closed.
+     */
+    fn get_body_framing(&self, method: &str) -> Result<BodyFraming, ClientError> {
+        if method.eq_ignore_ascii_case("HEAD")
+            || (method.eq_ignore_ascii_case("CONNECT") && (200..300).contains(&self.response_code))
+            || (100..200).contains(&self.response_code)
+            || self.response_code == 204
+            || self.response_code == 304 {
+            return Ok(BodyFraming::Empty);
+        }
+        if let Some(transfer_encoding) = self.headers.get_combined(TRANSFER_ENCODING) {
+            let final_coding = transfer_encoding.rsplit(',').next().unwrap_or_default().trim().to_string();
+            if final_coding.eq_ignore_ascii_case(CHUNKED) {
+                return Ok(BodyFraming::Chunked);
+            }
+            return Ok(BodyFraming::Close);
+        }
+        match self.headers.get_combined(CONTENT_LENGTH) {
+            Some(content_length) => Ok(BodyFraming::Length(Self::parse_content_length(&content_length)?)),
+            None => Ok(BodyFraming::Close)
+        }
+    }
+
+    /**
+     * Parse the Content-Length value. A list of identical values is accepted, as it may be the
+     * result of combining repeated headers.
+     * Returns an error if the value is not a number or the values differ.
+     */
+    pub(crate) fn parse_content_length(content_length: &str) -> Result<u64, ClientError> {
+        let mut length: Option<u64> = None;
+        for value in content_length.split(',').map(str::trim) {
+            if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(ClientError::new(ClientErrorType::InvalidContentLength, format!("Invalid Content-Length {:?}", content_length)));
+            }
+            let value = match value.parse::<u64>() {
+                Ok(value) => value,
+                Err(err) => return Err(ClientError::new(ClientErrorType::InvalidContentLength, err.to_string()))
+            };
+            if length.is_some_and(|length| length != value) {
+                return Err(ClientError::new(ClientErrorType::InvalidContentLength, format!("Conflicting Content-Length {:?}", content_length)));
+            }
+            length = Some(value);
+        }
+        length.ok_or(ClientError::new(ClientErrorType::InvalidContentLength, "Empty Content-Length".to_string()))
+    }
+
+    fn read_head<R: BufRead>(reader: &mut R) -> Result<ResponseHead, ClientError> {
+        let status_line = match read_line(reader)? {
+            Some(status_line) => status_line,
+            None => return Err(ClientError::new(ClientErrorType::NoResponse, "No response".to_string()))
+        };
+        let (version, response_code, reason) = Self::parse_status_line(&status_line)?;
+        let headers = read_fields(reader, status_line.len())?;
+        Ok(ResponseHead {
+            version,
+            response_code,
+            reason,
+            headers
+        })
+    }
+
+    /**
+     * Parse the status line.
+     * Example: HTTP/1.1 200 OK
+     *
+     * Returns the version, response code and reason phrase, or an error if the line is not a valid status line.
+     */
+    fn parse_status_line(line: &[u8]) -> Result<(String, u16, String), ClientError> {
+        let invalid_status_line = || ClientError::new(ClientErrorType::InvalidStatusLine, format!("Invalid status line {:?}", String::from_utf8_lossy(line)));
+        let mut parts = line.splitn(3, |byte| *byte == b' ');
+        let version = parts.next().unwrap_or_default();
+        let response_code = parts.next().unwrap_or_default();
+        let reason = parts.next().unwrap_or_default();
+        if version.len() != 8 || !version.starts_with(b"HTTP/") || !version[5].is_ascii_digit() || version[6] != b'.' || !version[7].is_ascii_digit() {
+            return Err(invalid_status_line());
+        }
+        if response_code.len() != 3 || !response_code.iter().all(u8::is_ascii_digit) {
+            return Err(invalid_status_line());
+        }
+        let response_code = String::from_utf8_lossy(response_code).parse::<u16>().map_err(|_| invalid_status_line())?;
+        if !(100..=999).contains(&response_code) {
+            return Err(invalid_status_line());
+        }
+        Ok((String::from_utf8_lossy(version).to_string(), response_code, String::from_utf8_lossy(reason).to_string()))
+    }
+
+}
+
+/**
+ * Reads header fields until an empty line. Used for both the header section and chunked trailers.
+ * Returns an error if the stream ends before the empty line or the fields are too large.
+ */
+fn read_fields<R: BufRead>(reader: &mut R, initial_size: usize) -> Result<HeaderMap, ClientError> {
+    let mut fields: HeaderMap = HeaderMap::new();
+    let mut section_size = initial_size;
+    loop {
+        let line = match read_line(reader)? {
+            Some(line) => line,
+            None => return Err(ClientError::new(ClientErrorType::UnexpectedEof, "Connection closed while reading headers".to_string()))
+        };
+        if line.is_empty() {
+            return Ok(fields);
+        }
+        section_size += line.len();
+        if section_size > MAX_HEADER_SECTION_SIZE {
+            return Err(ClientError::new(ClientErrorType::ResponseTooLarge, "Header section is too large".to_string()));
+        }
+        let field = get_header(&line)?;
+        fields.append(&field.0, &field.1);
+    }
+}
+
+/**
+ * Get header from line.
+ * Bytes that are not valid UTF-8 are replaced.
+ */
+fn get_header(line: &[u8]) -> Result<(String, String), ClientError> {
+    parse_field_line(&String::from_utf8_lossy(line))
+}
+
+/**
+ * Reads a line terminated by LF. A preceding CR is removed.
+ * Returns None if the reader is at the end of the stream, or an error if the line is too long
+ * or the stream ends in the middle of the line. A tls connection closed without close_notify
+ * is an end of the stream here, as lines are delimited.
+ */
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<Vec<u8>>, ClientError> {
+    let mut line: Vec<u8> = Vec::new();
+    let read_result = reader.take(MAX_LINE_LENGTH as u64 + 1).read_until(b'\n', &mut line);
+    match read_result {
+        Ok(0) => return Ok(None),
+        Ok(_) => {},
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof && line.is_empty() => return Ok(None),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => {},
+        Err(err) if matches!(err.kind(), ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted) => return Err(ClientError::new(ClientErrorType::ConnectionReset, err.to_string())),
+        Err(err) => return Err(ClientError::new(ClientErrorType::ReadError, err.to_string()))
+    }
+    if line.last() != Some(&b'\n') {
+        if line.len() > MAX_LINE_LENGTH {
+            return Err(ClientError::new(ClientErrorType::ResponseTooLarge, "Line is too long".to_string()));
+        }
+        return Err(ClientError::new(ClientErrorType::UnexpectedEof, "Connection closed in the middle of a line".to_string()));
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+/**
+ * How the end of the response body is determined.
+ */
+#[derive(Debug, PartialEq)]
+pub enum BodyFraming {
+    Empty,
+    Length(u64),
+    Chunked,
+    Close
+}
+
+/**
+ * State of the chunked transfer coding decoder.
+ */
+#[derive(Debug, PartialEq)]
+enum ChunkState {
+    Size,
+    Data(u64),
+    DataEnd,
+    Done
+}
+
+/**
+ * Reader for the response body.
+ * Reads exactly the body as framed by Content-Length or chunked transfer coding, leaving the
+ * reader positioned at the end of the response. Chunk extensions are ignored, and trailers
+ * are available once the body has been read.
+ */
+pub struct BodyReader<'a, R: BufRead> {
+    reader: &'a mut R,
+    body_framing: BodyFraming,
+    remaining: u64,
+    chunk_state: ChunkState,
+    pub trailers: HeaderMap
+}
+
+impl<'a, R: BufRead> BodyReader<'a, R> {
+    fn new(reader: &'a mut R, body_framing: BodyFraming) -> BodyReader<'a, R> {
+        let remaining = match body_framing {
+            BodyFraming::Length(length) => length,
+            _ => 0
+        };
+        BodyReader {
+            reader,
+            body_framing,
+            remaining,
+            chunk_state: ChunkState::Size,
+            trailers: HeaderMap::new()
+        }
+    }
+
+    fn read_length(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let max_read = buf.len().min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let read = self.reader.read(&mut buf[..max_read])?;
+        if read == 0 {
+            return Err(std::io::Error::new(ErrorKind::UnexpectedEof, format!("Connection closed with {} bytes of the body remaining", self.remaining)));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+
+    fn read_chunked(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            match self.chunk_state {
+                ChunkState::Size => {
+                    let chunk_size = self.read_chunk_size()?;
+                    if chunk_size == 0 {
+                        self.trailers = read_fields(self.reader, 0).map_err(get_io_error)?;
+                        self.chunk_state = ChunkState::Done;
+                    } else {
+                        self.chunk_state = ChunkState::Data(chunk_size);
+                    }
+                },
+                ChunkState::Data(remaining) => {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+                    let max_read = buf.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+                    let read = self.reader.read(&mut buf[..max_read])?;
+                    if read == 0 {
+                        return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Connection closed in the middle of a chunk"));
+                    }
+                    let remaining = remaining - read as u64;
+                    self.chunk_state = if remaining == 0 { ChunkState::DataEnd } else { ChunkState::Data(remaining) };
+                    return Ok(read);
+                },
+                ChunkState::DataEnd => {
+                    match read_line(self.reader).map_err(get_io_error)? {
+                        Some(line) if line.is_empty() => self.chunk_state = ChunkState::Size,
+                        Some(_) => return Err(std::io::Error::new(ErrorKind::InvalidData, "Missing line ending after chunk data")),
+                        None => return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Connection closed after chunk data"))
+                    }
+                },
+                ChunkState::Done => return Ok(0)
+            }
+        }
+    }
+
+    /**
+     * Reads a body delimited by closing the connection. A tls connection closed without
+     * close_notify is an error, as the body cannot be told apart from a truncated one.
+     */
+    fn read_close(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.reader.read(buf) {
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Connection closed without close_notify, the body may be truncated")),
+            read_result => read_result
+        }
+    }
+
+    /**
+     * Reads a chunk size line. The size is hexadecimal and may be followed by chunk extensions,
+     * which are ignored.
+     * Example: 1A;name=value
+     */
+    fn read_chunk_size(&mut self) -> std::io::Result<u64> {
+        let line = match read_line(self.reader).map_err(get_io_error)? {
+            Some(line) => line,
+            None => return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Connection closed before chunk size"))
+        };
+        let line = String::from_utf8_lossy(&line);
+        let chunk_size = line.split(';').next().unwrap_or_default().trim_end_matches([' ', '\t']);
+        if chunk_size.is_empty() || !chunk_size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, format!("Invalid chunk size line {:?}", line)));
+        }
+        match u64::from_str_radix(chunk_size, 16) {
+            Ok(chunk_size) => Ok(chunk_size),
+            Err(err) => Err(std::io::Error::new(ErrorKind::InvalidData, format!("Invalid chunk size {:?}: {}", chunk_size, err)))
+        }
+    }
+}
+
+impl<R: BufRead> Read for BodyReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.body_framing {
+            BodyFraming::Empty => Ok(0),
+            BodyFraming::Length(_) => self.read_length(buf),
+            BodyFraming::Chunked => self.read_chunked(buf),
+            BodyFraming::Close => self.read_close(buf)
+        }
+    }
+}
+
+/**
+ * Converts an error from parsing inside the body reader to an io error.
+ */
+fn get_io_error(err: ClientError) -> std::io::Error {
+    match err.error_type {
+        ClientErrorType::UnexpectedEof => std::io::Error::new(ErrorKind::UnexpectedEof, err.message),
+        ClientErrorType::ReadError | ClientErrorType::ConnectionReset => std::io::Error::other(err.message),
+        _ => std::io::Error::new(ErrorKind::InvalidData, err.message)
+    }
+}
+
+/**
+ * Converts an io error from the body reader to a client error.
+ */
+fn get_body_error(err: std::io::Error) -> ClientError {
+    match err.kind() {
+        ErrorKind::UnexpectedEof => ClientError::new(ClientErrorType::UnexpectedEof, err.to_string()),
+        ErrorKind::InvalidData => ClientError::new(ClientErrorType::InvalidChunkedEncoding, err.to_string()),
+        _ => ClientError::new(ClientErrorType::ReadError, err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{ BufReader, Cursor };
+
+    fn read_response(response: &[u8]) -> Result<HttpResponse, ClientError> {
+        HttpResponse::read(&mut BufReader::new(Cursor::new(response.to_vec())), "GET")
+    }
+
+    /**
+     * Reader ending like a tls connection closed without close_notify.
+     */
+    struct WithoutCloseNotify(Cursor<Vec<u8>>);
+
+    impl Read for WithoutCloseNotify {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.read(buf)? {
+                0 if !buf.is_empty() => Err(std::io::Error::new(ErrorKind::UnexpectedEof, "peer closed connection without sending TLS close_notify")),
+                read => Ok(read)
+            }
+        }
+    }
+
+    fn read_response_without_close_notify(response: &[u8]) -> Result<HttpResponse, ClientError> {
+        HttpResponse::read(&mut BufReader::new(WithoutCloseNotify(Cursor::new(response.to_vec()))), "GET")
+    }
+
+    #[test]
+    fn test_read_response() {
+        let http_response = read_response(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nHello\nworld").unwrap();
+        assert_eq!(http_response.version, "HTTP/1.1");
+        assert_eq!(http_response.response_code, 200);
+        assert_eq!(http_response.reason, "OK");
+        assert_eq!(http_response.headers.get("Content-Type").unwrap(), "text/plain");
+        assert_eq!(http_response.body, b"Hello\nworld");
+    }
+
+    #[test]
+    fn test_read_response_binary_body() {
+        let mut response = b"HTTP/1.1 200 OK\r\n\r\n".to_vec();
+        let body: Vec<u8> = vec![0x1f, 0x8b, 0x08, 0x00, 0xff, 0xfe, 0x0d, 0x0a, 0x00];
+        response.extend_from_slice(&body);
+        let http_response = read_response(&response).unwrap();
+        assert_eq!(http_response.body, body);
+    }
+
+    #[test]
+    fn test_read_response_bare_lf() {
+        let http_response = read_response(b"HTTP/1.0 404 Not Found\nServer: test\n\n").unwrap();
+        assert_eq!(http_response.response_code, 404);
+        assert_eq!(http_response.reason, "Not Found");
+        assert_eq!(http_response.headers.len(), 1);
+        assert!(http_response.body.is_empty());
+    }
+
+    #[test]
+    fn test_read_response_skips_interim_responses() {
+        let http_response = read_response(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n").unwrap();
+        assert_eq!(http_response.response_code, 204);
+        let mut response = b"HTTP/1.1 100 Continue\r\n\r\n".repeat(MAX_INTERIM_RESPONSES);
+        response.extend_from_slice(b"HTTP/1.1 204 No Content\r\n\r\n");
+        assert_eq!(read_response(&response).unwrap().response_code, 204);
+        let response = b"HTTP/1.1 100 Continue\r\n\r\n".repeat(MAX_INTERIM_RESPONSES + 1);
+        assert_eq!(read_response(&response).err().unwrap().error_type, ClientErrorType::TooManyInterimResponses);
+    }
+
+    #[test]
+    fn test_read_response_content_length() {
+        let mut reader = BufReader::new(Cursor::new(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nHelloHTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".to_vec()));
+        let http_response = HttpResponse::read(&mut reader, "GET").unwrap();
+        assert_eq!(http_response.body, b"Hello");
+        let http_response = HttpResponse::read(&mut reader, "GET").unwrap();
+        assert!(http_response.body.is_empty());
+        assert_eq!(HttpResponse::read(&mut reader, "GET").err().unwrap().error_type, ClientErrorType::NoResponse);
+    }
+
+    #[test]
+    fn test_read_response_without_close_notify() {
+        let http_response = read_response_without_close_notify(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello").unwrap();
+        assert_eq!(http_response.body, b"Hello");
+        let http_response = read_response_without_close_notify(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n0\r\n\r\n").unwrap();
+        assert_eq!(http_response.body, b"Hello");
+        let error = read_response_without_close_notify(b"HTTP/1.1 200 OK\r\n\r\nHello").err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::UnexpectedEof);
+        let error = read_response_without_close_notify(b"").err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::NoResponse);
+        let error = read_response_without_close_notify(b"HTTP/1.1 200 OK\r\nServer").err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_read_response_content_length_truncated() {
+        let error = read_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nHello").err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_read_response_invalid_content_length() {
+        let invalid_content_lengths = ["abc", "-1", "5, 6", "", "1 2"];
+        for content_length in invalid_content_lengths {
+            let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\nHello", content_length);
+            let error = read_response(response.as_bytes()).err().unwrap();
+            assert_eq!(error.error_type, ClientErrorType::InvalidContentLength);
+        }
+        let http_response = read_response(b"HTTP/1.1 200 OK\r\nContent-Length: 5, 5\r\n\r\nHello").unwrap();
+        assert_eq!(http_response.body, b"Hello");
+    }
+
+    #[test]
+    fn test_read_response_chunked() {
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nContent-Length: 100\r\n\r\n5;name=value\r\nHello\r\n7 \r\n, world\r\n0\r\nExpires: never\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n";
+        let mut reader = BufReader::new(Cursor::new(response.to_vec()));
+        let http_response = HttpResponse::read(&mut reader, "GET").unwrap();
+        assert_eq!(http_response.body, b"Hello, world");
+        assert_eq!(http_response.trailers.get("Expires").unwrap(), "never");
+        let http_response = HttpResponse::read(&mut reader, "GET").unwrap();
+        assert_eq!(http_response.response_code, 204);
+    }
+
+    #[test]
+    fn test_read_response_chunked_binary() {
+        let mut response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\n\r\n3\r\n".to_vec();
+        response.extend_from_slice(&[0x0d, 0x0a, 0x00]);
+        response.extend_from_slice(b"\r\n0\r\n\r\n");
+        let http_response = read_response(&response).unwrap();
+        assert_eq!(http_response.body, vec![0x0d, 0x0a, 0x00]);
+    }
+
+    #[test]
+    fn test_read_response_invalid_chunked() {
+        let invalid_responses: [&[u8]; 3] = [
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nxyz\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHelloXX\r\n0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nFFFFFFFFFFFFFFFFFF\r\n",
+        ];
+        for response in invalid_responses {
+            let error = read_response(response).err().unwrap();
+            assert_eq!(error.error_type, ClientErrorType::InvalidChunkedEncoding);
+        }
+        let error = read_response(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHel").err().unwrap();
+        assert_eq!(error.error_type, ClientErrorType::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_read_response_without_body() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n";
+        let http_response = HttpResponse::read(&mut BufReader::new(Cursor::new(response.to_vec())), "HEAD").unwrap();
+        assert!(http_response.body.is_empty());
+        let http_response = read_response(b"HTTP/1.1 304 Not Modified\r\nContent-Length: 5\r\n\r\n").unwrap();
+        assert!(http_response.body.is_empty());
+    }
+
+    #[test]
+    fn test_is_persistent() {
+        let persistent_responses: [(&[u8], bool); 7] = [
+            (b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n", true),
+            (b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: Close\r\n\r\n", false),
+            (b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: upgrade, close\r\n\r\n", false),
+            (b"HTTP/1.1 200 OK\r\n\r\n", false),
+            (b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nContent-Length: 0\r\n\r\n0\r\n\r\n", false),
+            (b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n", false),
+            (b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\nConnection: keep-alive\r\n\r\n", true),
+        ];
+        for (response, persistent) in persistent_responses {
+            assert_eq!(read_response(response).unwrap().is_persistent(), persistent);
+        }
+    }
+
+    #[test]
+    fn test_get_body_framing() {
+        let response_head = ResponseHead::read(&mut BufReader::new(Cursor::new(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked, gzip\r\n\r\n".to_vec()))).unwrap();
+        assert_eq!(response_head.get_body_framing("GET").unwrap(), BodyFraming::Close);
+        let response_head = ResponseHead::read(&mut BufReader::new(Cursor::new(b"HTTP/1.1 200 Connection established\r\n\r\n".to_vec()))).unwrap();
+        assert_eq!(response_head.get_body_framing("CONNECT").unwrap(), BodyFraming::Empty);
+        assert_eq!(response_head.get_body_framing("GET").unwrap(), BodyFraming::Close);
+    }
+
+    #[test]
+    fn test_read_response_empty_reason() {
+        let http_response = read_response(b"HTTP/1.1 200 \r\n\r\n").unwrap();
+        assert_eq!(http_response.reason, "");
+        let http_response = read_response(b"HTTP/1.1 200\r\n\r\n").unwrap();
+        assert_eq!(http_response.reason, "");
+    }
+
+    #[test]
+    fn test_read_response_header_values() {
+        let http_response = read_response(b"HTTP/1.1 301 Moved Permanently\r\nDate: Tue, 15 Nov 1994 08:12:31 GMT\r\nLocation:http://localhost:8080/ \r\nContent-Length: 0\r\n\r\n").unwrap();
+        assert_eq!(http_response.headers.get("date"), Some("Tue, 15 Nov 1994 08:12:31 GMT"));
+        assert_eq!(http_response.headers.get("location"), Some("http://localhost:8080/"));
+    }
+
+    #[test]
+    fn test_read_response_errors() {
+        assert_eq!(read_response(b"").err().unwrap().error_type, ClientErrorType::NoResponse);
+        assert_eq!(read_response(b"HTTP/1.1 200 OK\r\nServer: test\r\n").err().unwrap().error_type, ClientErrorType::UnexpectedEof);
+        assert_eq!(read_response(b"HTTP/1.1 200 OK").err().unwrap().error_type, ClientErrorType::UnexpectedEof);
+        assert_eq!(read_response(b"HTTP/1.1 200 OK\r\nInvalid header\r\n\r\n").err().unwrap().error_type, ClientErrorType::InvalidHeader);
+        assert_eq!(read_response(b"HTTP/1.1 200 OK\r\nX-Folded: a\r\n b\r\n\r\n").err().unwrap().error_type, ClientErrorType::InvalidHeader);
+        assert_eq!(read_response(b"HTTP/1.1 200 OK\r\nServer : test\r\n\r\n").err().unwrap().error_type, ClientErrorType::InvalidHeader);
+        let mut long_line = b"HTTP/1.1 200 OK\r\nX-Long: ".to_vec();
+        long_line.extend(vec![b'a'; MAX_LINE_LENGTH]);
+        assert_eq!(read_response(&long_line).err().unwrap().error_type, ClientErrorType::ResponseTooLarge);
+    }
+
+    #[test]
+    fn test_parse_status_line_invalid() {
+        let invalid_status_lines: [&[u8]; 7] = [b"", b"HTTP/1.1", b"HTTP/1.1 20 OK", b"HTTP/1.1 2000 OK", b"HTTP/1.1 abc OK", b"HTTX/1.1 200 OK", b"SSH-2.0-OpenSSH_9.6"];
+        for status_line in invalid_status_lines {
+            let error = ResponseHead::parse_status_line(status_line).err().unwrap();
+            assert_eq!(error.error_type, ClientErrorType::InvalidStatusLine);
+        }
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut reader = BufReader::new(Cursor::new(b"first\r\nsecond\nthird".to_vec()));
+        assert_eq!(read_line(&mut reader).unwrap().unwrap(), b"first");
+        assert_eq!(read_line(&mut reader).unwrap().unwrap(), b"second");
+        assert_eq!(read_line(&mut reader).err().unwrap().error_type, ClientErrorType::UnexpectedEof);
+        assert!(read_line(&mut reader).unwrap().is_none());
+    }
+}
@@ -0,0 +1,251 @@
+use crate::common::{ ClientError, ClientErrorType };
+use crate::common::base64;
+use crate::http::parser::Parser;
+
+/**
+ * Bare item of a structured field, as described in RFC 8941 section 3.3.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Item {
+    Integer(i64),
+    Decimal(f64),
+    String(String),
+    Token(String),
+    ByteSequence(Vec<u8>),
+    Boolean(bool)
+}
+
+impl Item {
+    pub(crate) fn serialize(&self) -> String {
+        match self {
+            Item::Integer(integer) => integer.to_string(),
+            Item::Decimal(decimal) => {
+                // Rounded to three fractional digits, without trailing zeros but with at least one digit.
+                let decimal = format!("{:.3}", decimal);
+                let decimal = decimal.trim_end_matches('0');
+                if decimal.ends_with('.') { format!("{}0", decimal) } else { decimal.to_string() }
+            },
+            Item::String(string) => format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\"")),
+            Item::Token(token) => token.clone(),
+            Item::ByteSequence(bytes) => format!(":{}:", base64::STANDARD.encode(bytes)),
+            Item::Boolean(boolean) => if *boolean { "?1".to_string() } else { "?0".to_string() }
+        }
+    }
+}
+
+/**
+ * Member of a structured field dictionary, an inner list or an item, with parameters.
+ */
+#[derive(Debug, PartialEq)]
+pub(crate) enum Member {
+    InnerList(Vec<(Item, Vec<(String, Item)>)>, Vec<(String, Item)>),
+    Item(Item, Vec<(String, Item)>)
+}
+
+/**
+ * Parses a structured field dictionary, as described in RFC 8941 section 4.2.2.
+ * A duplicate key replaces the value of the first, which keeps its position.
+ */
+pub(crate) fn parse_dictionary(value: &str) -> Result<Vec<(String, Member)>, ClientError> {
+    let mut parser = Parser::new(value);
+    let mut members: Vec<(String, Member)> = Vec::new();
+    parser.skip_whitespace();
+    while !parser.is_end() {
+        let key = parser.parse_key()?;
+        let member = if parser.consume(b'=') {
+            parser.parse_member()?
+        } else {
+            Member::Item(Item::Boolean(true), parser.parse_params()?)
+        };
+        insert(&mut members, key, member);
+        parser.skip_whitespace();
+        if parser.is_end() {
+            break;
+        }
+        if !parser.consume(b',') {
+            return Err(parser.get_error());
+        }
+        parser.skip_whitespace();
+        if parser.is_end() {
+            return Err(parser.get_error());
+        }
+    }
+    Ok(members)
+}
+
+/**
+ * Inserts the value for the key, replacing the value in place if the key is already there.
+ */
+fn insert<T>(entries: &mut Vec<(String, T)>, key: String, value: T) {
+    match entries.iter_mut().find(|entry| entry.0 == key) {
+        Some(entry) => entry.1 = value,
+        None => entries.push((key, value))
+    }
+}
+
+/**
+ * Methods of the parser for structured fields.
+ */
+impl Parser<'_> {
+    fn get_error(&self) -> ClientError {
+        ClientError::new(ClientErrorType::InvalidHeader, format!("Invalid structured field at position {}", self.position))
+    }
+
+    fn parse_key(&mut self) -> Result<String, ClientError> {
+        if !self.peek().is_some_and(|byte| byte.is_ascii_lowercase() || byte == b'*') {
+            return Err(self.get_error());
+        }
+        Ok(self.read_while(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || b"_-.*".contains(&byte)))
+    }
+
+    fn parse_member(&mut self) -> Result<Member, ClientError> {
+        if !self.consume(b'(') {
+            let item = self.parse_item()?;
+            return Ok(Member::Item(item, self.parse_params()?));
+        }
+        let mut items: Vec<(Item, Vec<(String, Item)>)> = Vec::new();
+        loop {
+            while self.consume(b' ') {}
+            if self.consume(b')') {
+                return Ok(Member::InnerList(items, self.parse_params()?));
+            }
+            let item = self.parse_item()?;
+            items.push((item, self.parse_params()?));
+            if !matches!(self.peek(), Some(b' ' | b')')) {
+                return Err(self.get_error());
+            }
+        }
+    }
+
+    fn parse_params(&mut self) -> Result<Vec<(String, Item)>, ClientError> {
+        let mut params: Vec<(String, Item)> = Vec::new();
+        while self.consume(b';') {
+            while self.consume(b' ') {}
+            let key = self.parse_key()?;
+            let item = if self.consume(b'=') { self.parse_item()? } else { Item::Boolean(true) };
+            insert(&mut params, key, item);
+        }
+        Ok(params)
+    }
+
+    fn parse_item(&mut self) -> Result<Item, ClientError> {
+        match self.peek() {
+            Some(b'"') => self.parse_string(),
+            Some(b':') => {
+                self.position += 1;
+                let encoded = self.read_while(|byte| byte.is_ascii_alphanumeric() || b"+/=".contains(&byte));
+                if !self.consume(b':') {
+                    return Err(self.get_error());
+                }
+                match base64::STANDARD.decode_lenient(&encoded) {
+                    Ok(bytes) => Ok(Item::ByteSequence(bytes)),
+                    Err(_) => Err(self.get_error())
+                }
+            },
+            Some(b'?') => {
+                self.position += 1;
+                match self.peek() {
+                    Some(b'0') | Some(b'1') => {
+                        self.position += 1;
+                        Ok(Item::Boolean(self.input[self.position - 1] == b'1'))
+                    },
+                    _ => Err(self.get_error())
+                }
+            },
+            Some(byte) if byte == b'-' || byte.is_ascii_digit() => self.parse_number(),
+            Some(byte) if byte.is_ascii_alphabetic() || byte == b'*' => {
+                Ok(Item::Token(self.read_while(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~:/".contains(&byte))))
+            },
+            _ => Err(self.get_error())
+        }
+    }
+
+    /**
+     * Parses an integer or a decimal, as described in RFC 8941 section 4.2.4.
+     * An integer has at most 15 digits, a decimal at most 12 before and 3 after the dot.
+     */
+    fn parse_number(&mut self) -> Result<Item, ClientError> {
+        let negative = self.consume(b'-');
+        let digits = self.read_while(|byte| byte.is_ascii_digit());
+        if digits.is_empty() {
+            return Err(self.get_error());
+        }
+        if !self.consume(b'.') {
+            if digits.len() > 15 {
+                return Err(self.get_error());
+            }
+            let integer: i64 = digits.parse().unwrap_or_default();
+            return Ok(Item::Integer(if negative { -integer } else { integer }));
+        }
+        let fraction = self.read_while(|byte| byte.is_ascii_digit());
+        if digits.len() > 12 || fraction.is_empty() || fraction.len() > 3 {
+            return Err(self.get_error());
+        }
+        let decimal: f64 = format!("{}.{}", digits, fraction).parse().unwrap_or_default();
+        Ok(Item::Decimal(if negative { -decimal } else { decimal }))
+    }
+
+    fn parse_string(&mut self) -> Result<Item, ClientError> {
+        let mut value = String::new();
+        self.position += 1;
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(Item::String(value));
+                },
+                Some(b'\\') if matches!(self.input.get(self.position + 1), Some(b'"' | b'\\')) => {
+                    value.push(self.input[self.position + 1] as char);
+                    self.position += 2;
+                },
+                Some(byte) if (0x20..0x7f).contains(&byte) && byte != b'\\' => {
+                    value.push(byte as char);
+                    self.position += 1;
+                },
+                _ => return Err(self.get_error())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_dictionary() {
+        let members = parse_dictionary("a=(\"x\" \"y\";sf);p=1;q=\"s\\\"t\", b=:AQI=:, c, d=?0;e=tok/en").unwrap();
+        assert_eq!(members.len(), 4);
+        assert_eq!(members[0].1, Member::InnerList(vec![(Item::String("x".to_string()), Vec::new()), (Item::String("y".to_string()), vec![("sf".to_string(), Item::Boolean(true))])], vec![("p".to_string(), Item::Integer(1)), ("q".to_string(), Item::String("s\"t".to_string()))]));
+        assert_eq!(members[1].1, Member::Item(Item::ByteSequence(vec![1, 2]), Vec::new()));
+        assert_eq!(members[2].1, Member::Item(Item::Boolean(true), Vec::new()));
+        assert_eq!(members[3].1, Member::Item(Item::Boolean(false), vec![("e".to_string(), Item::Token("tok/en".to_string()))]));
+        for invalid in ["A=1", "a=(\"x\"", "a=1,", "a=\"unterminated", "a=:AQI=", "a=1 b=2"] {
+            assert!(parse_dictionary(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_duplicate_keys() {
+        let members = parse_dictionary("a=1, b=2;p=1;q=2;p=3, a=3").unwrap();
+        assert_eq!(members, vec![
+            ("a".to_string(), Member::Item(Item::Integer(3), Vec::new())),
+            ("b".to_string(), Member::Item(Item::Integer(2), vec![("p".to_string(), Item::Integer(3)), ("q".to_string(), Item::Integer(2))]))
+        ]);
+    }
+
+    #[test]
+    fn test_parse_decimal() {
+        let members = parse_dictionary("a=1.5, b=-0.25, c=123456789012.123, d=999999999999999").unwrap();
+        assert_eq!(members[0].1, Member::Item(Item::Decimal(1.5), Vec::new()));
+        assert_eq!(members[1].1, Member::Item(Item::Decimal(-0.25), Vec::new()));
+        assert_eq!(members[2].1, Member::Item(Item::Decimal(123456789012.123), Vec::new()));
+        assert_eq!(members[3].1, Member::Item(Item::Integer(999999999999999), Vec::new()));
+        for invalid in ["a=1.", "a=1.2345", "a=1234567890123.1", "a=-.5", "a=1000000000000000"] {
+            assert!(parse_dictionary(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(Item::Decimal(1.5).serialize(), "1.5");
+        assert_eq!(Item::Decimal(2.0).serialize(), "2.0");
+        assert_eq!(Item::Decimal(-0.0625).serialize(), "-0.062");
+    }
+}
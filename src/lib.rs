@@ -0,0 +1,4 @@
+pub mod auth;
+pub mod connection;
+pub mod http;
+pub mod common;
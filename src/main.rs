@@ -1,51 +1,144 @@
-use common::Parameters;
-
 use clap::Parser;
+use url::Url;
 
-use crate::common::{ ClientError, ClientErrorType, Arguments };
-use crate::http::HttpRequest;
-use crate::http::HttpClient;
-
-mod connection;
-mod http;
-mod common;
+use http_client::auth::{ DigestAuth, OAuthAuth };
+use http_client::common::{ ClientError, ClientErrorType, Arguments, AuthType, Parameters };
+use http_client::connection::{ PoolKey, TlsConnection, TlsScanner };
+use http_client::http::{ verify_response_digests, HttpClient, HttpRequest, HttpResponse, RequestBody };
 
 fn main() -> Result<(), ClientError> {
     // Parsing arguments
     let arguments = Arguments::parse();
     // Converting arguments to parameters used by the client
     let parameters = Parameters::new(&arguments)?;
-    // Creating http client
-    let http_client = get_http_client(&parameters)?;
+    if parameters.tls_settings.insecure {
+        eprintln!("WARNING: --insecure is set, server certificates are not verified and connections can be intercepted");
+    }
     // Sending request
-    send_request(http_client, parameters)?;
+    send_request(parameters)?;
     // Ok
     Ok(())
 }
 
-fn send_request(http_client: HttpClient, parameters: Parameters) -> Result<(), ClientError> {
-    let http_request = HttpRequest::new(parameters.path, parameters.method, parameters.headers, parameters.body);
+fn send_request(mut parameters: Parameters) -> Result<(), ClientError> {
+    if let Some(expires) = parameters.presign {
+        println!("{}", parameters.get_presigned_url(expires)?);
+        return Ok(());
+    }
+    if parameters.scan {
+        let scanner = TlsScanner::new(parameters.connect_host.clone(), parameters.connect_port, parameters.connection_timeout, parameters.host.clone(), parameters.tls_settings.clone());
+        println!("Scan report :\n{}", scanner.scan()?);
+        return Ok(());
+    }
+    if let Some(pipeline) = parameters.pipeline {
+        return send_pipelined_requests(parameters, pipeline);
+    }
+    let mut digest_auth = match (&parameters.credentials, parameters.auth_type) {
+        (Some(credentials), AuthType::Digest) => Some(DigestAuth::new(credentials.clone())),
+        _ => None
+    };
+    let mut oauth = get_oauth(&parameters)?;
+    // Creating http client
+    let mut http_client = get_http_client(&parameters)?;
+    let http_request = parameters.get_request()?;
     println!("Http request : {:?}", http_request);
-    let http_result = http_client.send(http_request);
-    match http_result {
-        Ok(http_response) => {
-            println!("Http response : {:?}", http_response);
-            Ok(())
+    let origin = PoolKey::from_url(&parameters.url)?;
+    let http_response = match (&mut digest_auth, &mut oauth) {
+        (Some(digest_auth), _) => digest_auth.send(&mut http_client, http_request, &origin)?,
+        (None, Some(oauth)) => oauth.send(&mut http_client, http_request, &origin)?,
+        (None, None) => http_client.send(http_request)?
+    };
+    print_tls_info(&parameters, &http_response);
+    println!("Http response : {:?}", http_response);
+    verify_response(&parameters, &http_response)
+}
+
+/**
+ * Get the OAuth authentication of the parameters, printing the authorization url of the
+ * authorization code flow.
+ */
+fn get_oauth(parameters: &Parameters) -> Result<Option<OAuthAuth>, ClientError> {
+    match &parameters.oauth {
+        Some(config) => {
+            let config = config.clone().with_authorization_url_handler(Some(print_authorization_url));
+            Ok(Some(OAuthAuth::new(config, PoolKey::from_url(&parameters.url)?)))
         },
-        Err(err) => { Err(err) }
+        None => Ok(None)
+    }
+}
+
+fn print_authorization_url(url: &Url) {
+    eprintln!("Open this url in a browser to authorize the client: {}", url);
+}
+
+/**
+ * Prints the tls info of the connection the response was read from if asked to.
+ */
+fn print_tls_info(parameters: &Parameters, http_response: &HttpResponse) {
+    match &http_response.tls_info {
+        Some(tls_info) if parameters.tls_info => println!("Tls info :\n{}", tls_info),
+        _ => {}
     }
 }
 
+/**
+ * Verifies the digests of the response if asked to, and its message signature if a
+ * verify key is given.
+ */
+fn verify_response(parameters: &Parameters, http_response: &HttpResponse) -> Result<(), ClientError> {
+    if parameters.verify_digest {
+        for verified_digest in verify_response_digests(http_response, &parameters.method)? {
+            println!("Verified digest : {} {}", verified_digest.field, verified_digest.algorithm.get_name());
+        }
+    }
+    if let Some(signature_verifier) = &parameters.signature_verifier {
+        let signature_params = signature_verifier.verify(http_response)?;
+        println!("Verified signature : {}", signature_params.serialize());
+    }
+    Ok(())
+}
+
+fn send_pipelined_requests(mut parameters: Parameters, pipeline: usize) -> Result<(), ClientError> {
+    if matches!(parameters.body, Some(RequestBody::Stream(_))) {
+        return Err(ClientError::new(ClientErrorType::InvalidRequest, "A streamed body cannot be pipelined".to_string()));
+    }
+    let mut http_client = get_http_client(&parameters)?;
+    let mut oauth = get_oauth(&parameters)?;
+    let mut http_requests: Vec<HttpRequest> = Vec::new();
+    for _ in 0..pipeline {
+        let mut http_request = parameters.get_request()?;
+        if let Some(oauth) = &mut oauth {
+            oauth.authorize(&mut http_request, &PoolKey::from_url(&parameters.url)?)?;
+        }
+        http_requests.push(http_request);
+    }
+    println!("Http requests : {:?}", http_requests);
+    let http_responses = http_client.send_pipelined(http_requests)?;
+    for http_response in http_responses {
+        match http_response {
+            Ok(http_response) => {
+                print_tls_info(&parameters, &http_response);
+                println!("Http response : {:?}", http_response);
+                verify_response(&parameters, &http_response)?;
+            },
+            Err(err) => println!("Http response error : {:?}", err)
+        }
+    }
+    Ok(())
+}
+
 fn get_http_client(parameters: &Parameters) -> Result<HttpClient, ClientError> {
     match parameters.scheme.as_str() {
         "http" => { 
-            Ok(HttpClient::new( parameters.connect_host.clone(), parameters.connect_port.clone(), parameters.connection_timeout))
+            Ok(HttpClient::new( parameters.connect_host.clone(), parameters.connect_port, parameters.connection_timeout))
+         },
+        "https" if parameters.use_proxy => {
+            Err(ClientError::new(ClientErrorType::Unimplemented, "Https through a proxy is not supported".to_string()))
          },
         "https" => { 
-            Ok(HttpClient::new(parameters.connect_host.clone(), parameters.connect_port.clone(), parameters.connection_timeout))
+            let client_config = TlsConnection::get_client_config_with(&parameters.tls_settings)?;
+            Ok(HttpClient::new_tls(parameters.connect_host.clone(), parameters.connect_port, parameters.connection_timeout, parameters.host.clone(), client_config))
          },
         _ => { Err(ClientError::new(ClientErrorType::UnsupportedScheme, "Unsupported scheme".to_string())) }
     }
 }
-
-